@@ -0,0 +1,143 @@
+use std::iter::FromIterator;
+
+use indexmap::map::Iter;
+use indexmap::IndexMap;
+use serde::{Deserialize, Deserializer};
+use serde_derive::Serialize;
+use serde_yaml::Value;
+
+const GUI_X: &str = "gui-x";
+const GUI_Y: &str = "gui-y";
+
+/// Arbitrary annotations intepreted by things other than Juju itself
+///
+/// Can be set on the bundle itself, as well as on applications and machines.
+/// The most common ones are `gui-x` and `gui-y`, which position an application
+/// within the Juju GUI, and have typed accessors.
+#[derive(Debug, Clone, Serialize, PartialEq, Eq, Default)]
+#[serde(transparent)]
+pub struct Annotations(IndexMap<String, String>);
+
+impl Annotations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(String::as_str)
+    }
+
+    pub fn insert<K: Into<String>, V: Into<String>>(&mut self, key: K, value: V) -> Option<String> {
+        self.0.insert(key.into(), value.into())
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        self.0.shift_remove(key)
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.0.contains_key(key)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn iter(&self) -> Iter<'_, String, String> {
+        self.0.iter()
+    }
+
+    /// Position within the Juju GUI, if both coordinates are set and numeric
+    pub fn gui_position(&self) -> Option<(f64, f64)> {
+        let x = self.get(GUI_X)?.parse().ok()?;
+        let y = self.get(GUI_Y)?.parse().ok()?;
+
+        Some((x, y))
+    }
+
+    pub fn set_gui_position(&mut self, x: f64, y: f64) {
+        self.insert(GUI_X, x.to_string());
+        self.insert(GUI_Y, y.to_string());
+    }
+}
+
+impl<'de> Deserialize<'de> for Annotations {
+    /// Deserializes annotations, accepting unquoted numbers such as `gui-x: 300`
+    fn deserialize<D>(deserializer: D) -> Result<Annotations, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        use serde::de::Error;
+        let map = IndexMap::<String, Value>::deserialize(deserializer)?;
+
+        map.into_iter()
+            .map(|(k, v)| {
+                let v = match v {
+                    Value::String(s) => s,
+                    Value::Number(n) => n.to_string(),
+                    Value::Bool(b) => b.to_string(),
+                    _ => {
+                        return Err(Error::custom(format!(
+                            "Error deserializing Annotations: `{}` must be a string",
+                            k
+                        )))
+                    }
+                };
+
+                Ok((k, v))
+            })
+            .collect()
+    }
+}
+
+impl From<IndexMap<String, String>> for Annotations {
+    fn from(map: IndexMap<String, String>) -> Self {
+        Self(map)
+    }
+}
+
+impl<K: Into<String>, V: Into<String>> FromIterator<(K, V)> for Annotations {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        Self(
+            iter.into_iter()
+                .map(|(k, v)| (k.into(), v.into()))
+                .collect(),
+        )
+    }
+}
+
+impl<'a> IntoIterator for &'a Annotations {
+    type Item = (&'a String, &'a String);
+    type IntoIter = Iter<'a, String, String>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_yaml::from_str;
+
+    use super::*;
+
+    #[test]
+    fn test_gui_position() {
+        let mut annotations: Annotations =
+            from_str("{gui-x: '-106.5', gui-y: 300, colour: blue}").unwrap();
+
+        assert_eq!(annotations.gui_position(), Some((-106.5, 300.0)));
+        assert_eq!(annotations.get("colour"), Some("blue"));
+
+        annotations.set_gui_position(10.0, 20.5);
+        assert_eq!(annotations.get("gui-x"), Some("10"));
+        assert_eq!(annotations.gui_position(), Some((10.0, 20.5)));
+
+        annotations.remove("gui-y");
+        assert_eq!(annotations.gui_position(), None);
+    }
+}
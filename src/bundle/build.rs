@@ -0,0 +1,315 @@
+//! Building the charms in a bundle from source
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use ex::fs::create_dir_all;
+use indexmap::IndexMap;
+use rayon::prelude::*;
+use rayon::ThreadPoolBuilder;
+
+use super::lock::{BundleLock, LockedApplication};
+use super::{source_path, Application, Bundle};
+use crate::charm_source::{Artifact, Base, CharmSource};
+use crate::charm_url::CharmURL;
+use crate::error::JujuError;
+
+/// Something that happened while building a bundle, passed to `BuildOptions::progress`
+#[derive(Debug)]
+pub enum BuildEvent<'a> {
+    /// Started building the named application's charm
+    Started(&'a str),
+
+    /// Finished building the named application's charm
+    Built(&'a str, &'a CharmURL),
+
+    /// Failed to build the named application's charm
+    Failed(&'a str, &'a JujuError),
+}
+
+/// A callback for `BuildOptions::progress`
+pub type ProgressFn = Box<dyn Fn(&BuildEvent) + Send + Sync>;
+
+/// Options for `Bundle::build_with`
+#[derive(Default)]
+pub struct BuildOptions {
+    /// Pass `--destructive-mode` to `charmcraft pack`
+    pub destructive_mode: bool,
+
+    /// How many charms to build at once
+    ///
+    /// Defaults to one per CPU.
+    pub jobs: Option<usize>,
+
+    /// Where to write each application's build log, as `<application>.log`
+    ///
+    /// If unset, build output goes straight to the terminal.
+    pub log_dir: Option<PathBuf>,
+
+    /// Called as each application's build starts, finishes or fails
+    pub progress: Option<ProgressFn>,
+
+    /// Keep the charms that did build if others fail, instead of returning an error
+    ///
+    /// Failures are listed in the returned `BuildReport`.
+    pub keep_going: bool,
+
+    /// Pack charms with `CharmSource::pack_native` instead of charmcraft
+    ///
+    /// Only works for charms that don't need anything compiled, see
+    /// `JujuError::CannotPackNatively`.
+    pub native: bool,
+
+    /// The wheels to install into natively packed charms
+    pub wheelhouse: Option<PathBuf>,
+
+    /// Write `bundle.lock` next to the bundle for the applications that built
+    ///
+    /// See `LockedApplication::built`.
+    pub write_lock: bool,
+}
+
+impl BuildOptions {
+    fn report(&self, event: BuildEvent) {
+        if let Some(progress) = &self.progress {
+            progress(&event);
+        }
+    }
+}
+
+/// An application whose charm couldn't be built
+#[derive(Debug)]
+pub struct BuildFailure {
+    pub application: String,
+    pub error: JujuError,
+
+    /// The build log, if one was written
+    pub log: Option<PathBuf>,
+}
+
+impl fmt::Display for BuildFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.application, self.error)?;
+
+        if let Some(log) = &self.log {
+            write!(f, " (log: {})", log.display())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Lists build failures, one per line
+pub(crate) fn describe_failures(failures: &[BuildFailure]) -> String {
+    failures.iter().map(|f| format!("\n  {}", f)).collect()
+}
+
+/// The charms built by `Bundle::build_with`, and any that failed
+#[derive(Debug, Default)]
+pub struct BuildReport {
+    /// The path to each built charm, keyed by application name
+    pub built: IndexMap<String, CharmURL>,
+
+    pub failed: Vec<BuildFailure>,
+}
+
+impl BuildReport {
+    /// Whether every charm was built
+    pub fn is_success(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+/// The outcome of building a single application, along with the charm and where it
+/// was built from, if it was built
+type Built = Result<(Application, Option<(CharmSource, String)>), JujuError>;
+
+impl Bundle {
+    /// The series and architecture that an application's charm has to run on
+    ///
+    /// The series is the application's, or the bundle's, and the architecture is the
+    /// one in its constraints. Series that don't map to a base, such as `kubernetes`,
+    /// are `None` so that they match a charm built for any series.
+    pub(crate) fn target_platform(
+        &self,
+        application: &Application,
+    ) -> (Option<String>, Option<String>) {
+        let series = application
+            .series
+            .clone()
+            .or_else(|| self.series.as_ref().map(ToString::to_string))
+            .filter(|s| Base::is_known_series(s));
+        let arch = application
+            .constraints
+            .as_ref()
+            .and_then(|c| c.arch.clone());
+
+        (series, arch)
+    }
+
+    /// Builds a single application's charm, if it has a source
+    ///
+    /// Of the charms built, the one for `Bundle::target_platform` is used.
+    fn build_application(
+        &self,
+        name: &str,
+        application: &Application,
+        source: Option<String>,
+        path: &str,
+        opts: &BuildOptions,
+        log: Option<&Path>,
+    ) -> Built {
+        match (&application.charm, source) {
+            // Either `charm` or `source` must be set
+            (None, None) => Err(JujuError::MissingSourceError(name.into())),
+
+            // If the charm source was defined and either the `--build` flag was passed, or
+            // if there's no `charm` property, build the charm
+            (_, Some(source)) => {
+                opts.report(BuildEvent::Started(name));
+
+                let charm = CharmSource::load(source_path(&source, path))?;
+                let artifacts = if opts.native {
+                    charm.pack_native_cached(opts.wheelhouse.as_deref())?
+                } else {
+                    charm.build_cached(opts.destructive_mode, log)?
+                };
+
+                let (series, arch) = self.target_platform(application);
+
+                let artifact = Artifact::select(&artifacts, series.as_deref(), arch.as_deref())
+                    .ok_or_else(|| {
+                        JujuError::NoMatchingArtifact(
+                            name.into(),
+                            format!(
+                                "{}/{}",
+                                series.as_deref().unwrap_or("any series"),
+                                arch.as_deref().unwrap_or("any architecture")
+                            ),
+                        )
+                    })?;
+                let artifact = CharmURL::from_path(&artifact.path);
+
+                let mut new_application = application.clone();
+                new_application.resources =
+                    charm.resources_with_defaults(&new_application.resources)?;
+
+                opts.report(BuildEvent::Built(name, &artifact));
+                new_application.charm = Some(artifact);
+
+                Ok((new_application, Some((charm, source))))
+            }
+
+            // If a charm URL was defined and charm source isn't available
+            // locally, use the charm URL
+            (Some(_), None) => Ok((application.clone(), None)),
+        }
+    }
+
+    /// Builds the bundle's charms from source, and points the bundle at the built charms
+    ///
+    /// `path` is the path to the bundle, which relative `source` paths are resolved
+    /// against. `build_apps` limits which applications are built, optionally with
+    /// a different source for each. Charms are built in parallel, and a failed
+    /// build doesn't stop the others. If any fail, the bundle is left unchanged and
+    /// `JujuError::BuildFailed` lists every failure, unless `BuildOptions::keep_going`
+    /// is set. In that case, the applications that built are still updated, and the
+    /// failures are returned in the report.
+    ///
+    /// If `BuildOptions::write_lock` is set, `bundle.lock` is written next to the
+    /// bundle, pinning the applications that built to a hash of their source.
+    pub fn build_with(
+        &mut self,
+        path: &str,
+        build_apps: Option<HashMap<String, Option<String>>>,
+        opts: &BuildOptions,
+    ) -> Result<BuildReport, JujuError> {
+        if let Some(log_dir) = &opts.log_dir {
+            create_dir_all(log_dir)?;
+        }
+
+        let mut pool = ThreadPoolBuilder::new();
+        if let Some(jobs) = opts.jobs {
+            pool = pool.num_threads(jobs);
+        }
+        let pool = pool.build().map_err(std::io::Error::other)?;
+
+        let map = |(name, application): (&String, &Application)| {
+            let source = match &build_apps {
+                Some(app) => app
+                    .get(name)
+                    .cloned()
+                    .and_then(|source| source.or_else(|| application.source(name, path))),
+                None => application.source(name, path),
+            };
+            let log = opts
+                .log_dir
+                .as_ref()
+                .map(|dir| dir.join(format!("{}.log", name)));
+
+            let built =
+                self.build_application(name, application, source, path, opts, log.as_deref());
+            if let Err(err) = &built {
+                opts.report(BuildEvent::Failed(name, err));
+            }
+
+            (name.clone(), (built, log))
+        };
+
+        let results: IndexMap<String, (Built, Option<PathBuf>)> =
+            pool.install(|| self.applications.par_iter().map(map).collect());
+
+        let mut built = IndexMap::new();
+        let mut failures = vec![];
+
+        for (name, (result, log)) in results {
+            match result {
+                Ok(b) => {
+                    built.insert(name, b);
+                }
+                Err(error) => failures.push(BuildFailure {
+                    application: name,
+                    error,
+                    log: log.filter(|l| l.exists()),
+                }),
+            }
+        }
+
+        if !failures.is_empty() && !opts.keep_going {
+            return Err(JujuError::BuildFailed(failures));
+        }
+
+        for (name, (app, _)) in &built {
+            self.applications[name] = app.clone();
+        }
+
+        // Record what was built, along with a hash of the source it was built from
+        let mut lock = self.lock();
+        lock.applications
+            .retain(|name, _| !failures.iter().any(|f| &f.application == name));
+
+        let mut report = BuildReport::default();
+
+        for (name, (app, built)) in &built {
+            if let (Some((charm, source)), Some(artifact)) = (built, &app.charm) {
+                report.built.insert(name.clone(), artifact.clone());
+
+                if opts.write_lock {
+                    lock.applications.insert(
+                        name.clone(),
+                        LockedApplication::built(app, charm, source, path)?,
+                    );
+                }
+            }
+        }
+
+        if opts.write_lock {
+            lock.save(BundleLock::path_for(path))?;
+        }
+
+        report.failed = failures;
+
+        Ok(report)
+    }
+}
@@ -0,0 +1,683 @@
+//! Computing the steps needed to deploy a bundle, as done by `juju deploy --dry-run`
+//!
+//! Follows the same approach as Juju's `bundlechanges` package: applications are
+//! deployed first, then machines are added, then relations, and finally units are
+//! placed onto machines. Each change has an ID such as `deploy-1`, and may refer
+//! to the results of earlier changes with references such as `$addMachines-4`.
+
+use std::fmt;
+
+use indexmap::IndexMap;
+
+use super::{
+    Annotations, Application, Bundle, BundleRelation, Constraints, ContainerType, DeviceDirective,
+    Placement, RelationEndpoint, StorageDirective, Value,
+};
+use crate::charm_url::CharmURL;
+use crate::status::Status;
+
+/// What a set of annotations is for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntityType {
+    Application,
+    Machine,
+}
+
+/// A single step of a deployment, named after the Juju API methods that perform them
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    AddCharm {
+        charm: String,
+        series: Option<String>,
+        channel: Option<String>,
+        revision: Option<u32>,
+    },
+    Deploy {
+        /// Reference to the `addCharm` change for the application's charm
+        charm: String,
+        application: String,
+        series: Option<String>,
+        channel: Option<String>,
+        options: IndexMap<String, Value>,
+        constraints: Option<Constraints>,
+        storage: IndexMap<String, StorageDirective>,
+        devices: IndexMap<String, DeviceDirective>,
+        resources: IndexMap<String, String>,
+        endpoint_bindings: IndexMap<String, String>,
+    },
+    AddMachines {
+        series: Option<String>,
+        constraints: Option<Constraints>,
+        container_type: Option<ContainerType>,
+
+        /// The machine or unit to create a container on
+        parent: Option<String>,
+    },
+    AddUnit {
+        application: String,
+
+        /// Where to place the unit, or `None` for a new machine
+        to: Option<String>,
+    },
+    AddRelation {
+        endpoint1: String,
+        endpoint2: String,
+    },
+    SetAnnotations {
+        entity: String,
+        entity_type: EntityType,
+        annotations: Annotations,
+    },
+    Expose {
+        application: String,
+    },
+    UpgradeCharm {
+        application: String,
+
+        /// Reference to the `addCharm` change for the new charm
+        charm: String,
+        channel: Option<String>,
+    },
+}
+
+impl Step {
+    /// The name of the step, as used by Juju
+    pub fn method(&self) -> &'static str {
+        match self {
+            Step::AddCharm { .. } => "addCharm",
+            Step::Deploy { .. } => "deploy",
+            Step::AddMachines { .. } => "addMachines",
+            Step::AddUnit { .. } => "addUnit",
+            Step::AddRelation { .. } => "addRelation",
+            Step::SetAnnotations { .. } => "setAnnotations",
+            Step::Expose { .. } => "expose",
+            Step::UpgradeCharm { .. } => "upgradeCharm",
+        }
+    }
+}
+
+/// A change to make to a model
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    /// Unique ID of the change, such as `deploy-1`
+    pub id: String,
+
+    /// IDs of changes that must be made before this one
+    pub requires: Vec<String>,
+
+    pub step: Step,
+
+    /// Human-readable description, as shown by `juju deploy --dry-run`
+    description: String,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.description)
+    }
+}
+
+/// An application that is already deployed
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct DeployedApplication {
+    pub charm: Option<CharmURL>,
+    pub units: u32,
+    pub exposed: bool,
+    pub annotations: Annotations,
+}
+
+/// The parts of a model's current state that affect which changes are needed
+///
+/// Can be created from a bundle exported with `juju export-bundle`, or from a
+/// status snapshot. `ModelState::default()` is an empty model.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ModelState {
+    pub applications: IndexMap<String, DeployedApplication>,
+
+    /// IDs of existing machines
+    ///
+    /// Bundle machines with the same ID are treated as already existing, as with
+    /// `juju deploy --map-machines=existing`.
+    pub machines: Vec<String>,
+
+    pub relations: Vec<BundleRelation>,
+}
+
+impl From<&Bundle> for ModelState {
+    fn from(bundle: &Bundle) -> Self {
+        Self {
+            applications: bundle
+                .applications
+                .iter()
+                .map(|(name, app)| {
+                    let deployed = DeployedApplication {
+                        charm: app.charm.clone(),
+                        units: app.scale,
+                        exposed: app.expose,
+                        annotations: app.annotations.clone(),
+                    };
+                    (name.clone(), deployed)
+                })
+                .collect(),
+            machines: bundle.machines.keys().cloned().collect(),
+            relations: bundle.relations.clone(),
+        }
+    }
+}
+
+impl From<&Status> for ModelState {
+    fn from(status: &Status) -> Self {
+        let mut relations: Vec<BundleRelation> = vec![];
+
+        for (name, app) in &status.applications {
+            for (endpoint, related) in &app.relations {
+                for other in related {
+                    // Peer relations aren't part of bundles
+                    if other.application() == name {
+                        continue;
+                    }
+
+                    let relation = BundleRelation::new(
+                        RelationEndpoint::new(name, Some(endpoint)),
+                        RelationEndpoint::new(other.application(), None),
+                    );
+
+                    if !relations.iter().any(|r| relations_match(r, &relation)) {
+                        relations.push(relation);
+                    }
+                }
+            }
+        }
+
+        Self {
+            applications: status
+                .applications
+                .iter()
+                .map(|(name, app)| {
+                    let charm = CharmURL::parse(&app.charm)
+                        .ok()
+                        .map(|url| match url.revision {
+                            Some(_) => url,
+                            None => url.with_revision(app.charm_rev),
+                        });
+
+                    let deployed = DeployedApplication {
+                        charm,
+                        units: app.units.len() as u32,
+                        exposed: app.exposed,
+                        annotations: Annotations::new(),
+                    };
+                    (name.clone(), deployed)
+                })
+                .collect(),
+            machines: status.machines.keys().cloned().collect(),
+            relations,
+        }
+    }
+}
+
+/// Whether two relation endpoints could be the same, treating a missing endpoint name as a wildcard
+fn endpoints_match(a: &RelationEndpoint, b: &RelationEndpoint) -> bool {
+    a.application == b.application
+        && (a.endpoint.is_none() || b.endpoint.is_none() || a.endpoint == b.endpoint)
+}
+
+fn relations_match(a: &BundleRelation, b: &BundleRelation) -> bool {
+    (endpoints_match(&a.left, &b.left) && endpoints_match(&a.right, &b.right))
+        || (endpoints_match(&a.left, &b.right) && endpoints_match(&a.right, &b.left))
+}
+
+/// Whether a deployed charm needs upgrading to match the bundle
+///
+/// Only explicit differences count, so a bundle charm without a revision
+/// doesn't trigger an upgrade of a deployed charm with one. The wanted revision
+/// is either part of the charm URL or given separately as `revision`.
+fn needs_upgrade(
+    deployed: &Option<CharmURL>,
+    wanted: &Option<CharmURL>,
+    revision: Option<u32>,
+) -> bool {
+    match (deployed, wanted) {
+        (Some(d), Some(w)) => {
+            let revision = w.revision.or(revision);
+            d.name != w.name || (revision.is_some() && d.revision != revision)
+        }
+        _ => false,
+    }
+}
+
+/// Something that a unit can be placed on, along with a description of it
+struct Target {
+    reference: String,
+    description: String,
+}
+
+/// Builds up the list of changes for a bundle
+struct Planner<'a> {
+    bundle: &'a Bundle,
+    model: &'a ModelState,
+    changes: Vec<Change>,
+
+    /// IDs of `addCharm` changes, keyed by charm
+    charms: IndexMap<String, String>,
+
+    /// IDs of `deploy` changes, keyed by application
+    deploys: IndexMap<String, String>,
+
+    /// Machines defined in the bundle, keyed by their ID in the bundle
+    machines: IndexMap<String, Target>,
+
+    /// References to the units added so far, keyed by application and unit number
+    units: IndexMap<String, IndexMap<u32, String>>,
+}
+
+/// Gets the change ID from a reference such as `$addMachines-4`
+fn requirement(reference: &str) -> Option<String> {
+    reference.strip_prefix('$').map(String::from)
+}
+
+impl<'a> Planner<'a> {
+    fn add(&mut self, step: Step, requires: Vec<String>, description: String) -> String {
+        let id = format!("{}-{}", step.method(), self.changes.len());
+
+        self.changes.push(Change {
+            id: id.clone(),
+            requires,
+            step,
+            description,
+        });
+
+        id
+    }
+
+    fn series(&self, series: &Option<String>) -> Option<String> {
+        series
+            .clone()
+            .or_else(|| self.bundle.series.as_ref().map(ToString::to_string))
+    }
+
+    fn add_charm(&mut self, app: &Application, charm: &str, series: &Option<String>) -> String {
+        let key = match app.revision {
+            Some(revision) => format!("{} revision {}", charm, revision),
+            None => charm.into(),
+        };
+
+        if let Some(id) = self.charms.get(&key) {
+            return id.clone();
+        }
+
+        let mut description = format!("upload charm {}", charm);
+        if let Some(series) = series {
+            description.push_str(&format!(" for series {}", series));
+        }
+        if let Some(channel) = &app.channel {
+            description.push_str(&format!(" from channel {}", channel));
+        }
+        if let Some(revision) = app.revision {
+            description.push_str(&format!(" at revision {}", revision));
+        }
+
+        let step = Step::AddCharm {
+            charm: charm.into(),
+            series: series.clone(),
+            channel: app.channel.clone(),
+            revision: app.revision,
+        };
+        let id = self.add(step, vec![], description);
+        self.charms.insert(key, id.clone());
+        id
+    }
+
+    fn handle_applications(&mut self) {
+        for (name, app) in &self.bundle.applications {
+            let charm = match (&app.charm, &app.source) {
+                (Some(charm), _) => charm.to_string(),
+                (None, Some(source)) => source.clone(),
+                (None, None) => name.clone(),
+            };
+            let series = self.series(&app.series);
+            let existing = self.model.applications.get(name);
+            let mut requires = vec![];
+
+            match existing {
+                None => {
+                    let charm_id = self.add_charm(app, &charm, &series);
+
+                    let step = Step::Deploy {
+                        charm: format!("${}", charm_id),
+                        application: name.clone(),
+                        series: series.clone(),
+                        channel: app.channel.clone(),
+                        options: app
+                            .options
+                            .iter()
+                            .chain(app.config.iter())
+                            .map(|(k, v)| (k.clone(), v.clone()))
+                            .collect(),
+                        constraints: app.constraints.clone(),
+                        storage: app.storage.clone(),
+                        devices: app.devices.clone(),
+                        resources: app.resources.clone(),
+                        endpoint_bindings: app.endpoint_bindings.clone(),
+                    };
+                    let description = format!("deploy application {} using {}", name, charm);
+                    let id = self.add(step, vec![charm_id], description);
+
+                    self.deploys.insert(name.clone(), id.clone());
+                    requires.push(id);
+                }
+                Some(existing) if needs_upgrade(&existing.charm, &app.charm, app.revision) => {
+                    let charm_id = self.add_charm(app, &charm, &series);
+
+                    let step = Step::UpgradeCharm {
+                        application: name.clone(),
+                        charm: format!("${}", charm_id),
+                        channel: app.channel.clone(),
+                    };
+                    let description = format!("upgrade {} to use charm {}", name, charm);
+                    self.add(step, vec![charm_id], description);
+                }
+                Some(_) => {}
+            }
+
+            if app.expose && !existing.map(|e| e.exposed).unwrap_or(false) {
+                let step = Step::Expose {
+                    application: name.clone(),
+                };
+                let description = format!("expose all endpoints of {}", name);
+                self.add(step, requires.clone(), description);
+            }
+
+            let annotated = existing.map(|e| e.annotations == app.annotations);
+            if !app.annotations.is_empty() && annotated != Some(true) {
+                let step = Step::SetAnnotations {
+                    entity: name.clone(),
+                    entity_type: EntityType::Application,
+                    annotations: app.annotations.clone(),
+                };
+                let description = format!("set annotations for {}", name);
+                self.add(step, requires, description);
+            }
+        }
+    }
+
+    fn handle_machines(&mut self) {
+        for (id, machine) in &self.bundle.machines {
+            if self.model.machines.contains(id) {
+                let target = Target {
+                    reference: id.clone(),
+                    description: format!("existing machine {}", id),
+                };
+                self.machines.insert(id.clone(), target);
+                continue;
+            }
+
+            let step = Step::AddMachines {
+                series: self.series(&machine.series),
+                constraints: machine.constraints.clone(),
+                container_type: None,
+                parent: None,
+            };
+            let change_id = self.add(step, vec![], format!("add new machine {}", id));
+
+            if !machine.annotations.is_empty() {
+                let step = Step::SetAnnotations {
+                    entity: format!("${}", change_id),
+                    entity_type: EntityType::Machine,
+                    annotations: machine.annotations.clone(),
+                };
+                let description = format!("set annotations for new machine {}", id);
+                self.add(step, vec![change_id.clone()], description);
+            }
+
+            let target = Target {
+                reference: format!("${}", change_id),
+                description: format!("new machine {}", id),
+            };
+            self.machines.insert(id.clone(), target);
+        }
+    }
+
+    fn handle_relations(&mut self) {
+        for relation in &self.bundle.relations {
+            if self
+                .model
+                .relations
+                .iter()
+                .any(|r| relations_match(r, relation))
+            {
+                continue;
+            }
+
+            let requires = relation
+                .applications()
+                .iter()
+                .filter_map(|app| self.deploys.get(*app).cloned())
+                .collect();
+
+            let step = Step::AddRelation {
+                endpoint1: relation.left.to_string(),
+                endpoint2: relation.right.to_string(),
+            };
+            let description = format!("add relation {} - {}", relation.left, relation.right);
+            self.add(step, requires, description);
+        }
+    }
+
+    /// Orders applications so that units placed alongside another application's
+    /// units are added after them
+    fn unit_order(&self) -> Vec<&'a String> {
+        let mut pending: Vec<_> = self.bundle.applications.keys().collect();
+        let mut ordered = vec![];
+
+        while !pending.is_empty() {
+            let ready = pending
+                .iter()
+                .position(|name| {
+                    self.bundle.applications[*name].to.iter().all(|p| match p {
+                        Placement::Unit { application, .. } => {
+                            application == *name
+                                || ordered.contains(&application)
+                                || !pending.contains(&application)
+                        }
+                        _ => true,
+                    })
+                })
+                .unwrap_or(0);
+
+            ordered.push(pending.remove(ready));
+        }
+
+        ordered
+    }
+
+    /// Adds a container for a unit, returning where to place the unit
+    fn add_container(
+        &mut self,
+        app: &Application,
+        kind: &ContainerType,
+        parent: Option<Target>,
+    ) -> Target {
+        let (parent_ref, parent_description) = match parent {
+            Some(p) => (Some(p.reference), p.description),
+            None => (None, "new machine".to_string()),
+        };
+        let requires = parent_ref
+            .as_deref()
+            .and_then(requirement)
+            .into_iter()
+            .collect();
+
+        let step = Step::AddMachines {
+            series: self.series(&app.series),
+            constraints: app.constraints.clone(),
+            container_type: Some(*kind),
+            parent: parent_ref,
+        };
+        let description = format!("{} container on {}", kind, parent_description);
+        let id = self.add(step, requires, format!("add {}", description));
+
+        Target {
+            reference: format!("${}", id),
+            description,
+        }
+    }
+
+    /// Finds an existing or newly added unit of an application
+    fn unit(&self, application: &str, index: u32) -> Option<Target> {
+        let unit = format!("{}/{}", application, index);
+        let description = format!("the machine of unit {}", unit);
+
+        if let Some(reference) = self.units.get(application).and_then(|u| u.get(&index)) {
+            return Some(Target {
+                reference: reference.clone(),
+                description,
+            });
+        }
+
+        let existing = self.model.applications.get(application)?;
+
+        if index < existing.units {
+            Some(Target {
+                reference: unit,
+                description,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// How many units the application will have, whether deployed already or not
+    fn unit_count(&self, application: &str) -> u32 {
+        let wanted = self
+            .bundle
+            .applications
+            .get(application)
+            .map_or(0, |a| a.scale);
+        let existing = self
+            .model
+            .applications
+            .get(application)
+            .map_or(0, |a| a.units);
+
+        wanted.max(existing)
+    }
+
+    fn place_unit(&mut self, app: &Application, index: u32) -> Option<Target> {
+        let machine = |machines: &IndexMap<String, Target>, id: u32| {
+            machines.get(&id.to_string()).map_or_else(
+                || Target {
+                    reference: id.to_string(),
+                    description: format!("existing machine {}", id),
+                },
+                |t| Target {
+                    reference: t.reference.clone(),
+                    description: t.description.clone(),
+                },
+            )
+        };
+
+        match app.placement(index)? {
+            Placement::Machine(id) => Some(machine(&self.machines, *id)),
+            Placement::New => {
+                let step = Step::AddMachines {
+                    series: self.series(&app.series),
+                    constraints: app.constraints.clone(),
+                    container_type: None,
+                    parent: None,
+                };
+                let id = self.add(step, vec![], "add new machine".into());
+
+                Some(Target {
+                    reference: format!("${}", id),
+                    description: "new machine".into(),
+                })
+            }
+            Placement::Container { kind, machine: id } => {
+                let parent = id.map(|id| machine(&self.machines, id));
+                Some(self.add_container(app, kind, parent))
+            }
+            Placement::Unit {
+                container,
+                application,
+                unit,
+            } => {
+                // Without a unit number, units are placed round-robin alongside the
+                // application's units, starting with the unit with the same number
+                let index = match (unit, self.unit_count(application)) {
+                    (Some(unit), _) => *unit,
+                    (None, 0) => index,
+                    (None, count) => index % count,
+                };
+                let target = self.unit(application, index);
+
+                match container {
+                    Some(kind) => Some(self.add_container(app, kind, target)),
+                    None => target,
+                }
+            }
+            Placement::Zone(zone) => Some(Target {
+                reference: format!("zone={}", zone),
+                description: format!("new machine in zone {}", zone),
+            }),
+        }
+    }
+
+    fn handle_units(&mut self) {
+        for name in self.unit_order() {
+            let app = &self.bundle.applications[name];
+            let existing = self.model.applications.get(name).map_or(0, |e| e.units);
+
+            for index in existing..app.scale {
+                let unit = format!("{}/{}", name, index);
+                let target = self.place_unit(app, index);
+
+                let mut requires: Vec<_> = self.deploys.get(name).cloned().into_iter().collect();
+                requires.extend(target.as_ref().and_then(|t| requirement(&t.reference)));
+
+                let description = match &target {
+                    Some(t) => format!("add unit {} to {}", unit, t.description),
+                    None => format!("add unit {} to new machine", unit),
+                };
+
+                let step = Step::AddUnit {
+                    application: name.clone(),
+                    to: target.map(|t| t.reference),
+                };
+                let id = self.add(step, requires, description);
+
+                self.units
+                    .entry(name.clone())
+                    .or_default()
+                    .insert(index, format!("${}", id));
+            }
+        }
+    }
+}
+
+impl Bundle {
+    /// Calculates the changes needed to deploy this bundle to a model
+    ///
+    /// Equivalent to `juju deploy --dry-run`. Applications that already exist in
+    /// `model` are only upgraded if their charm differs, and only units beyond
+    /// the application's current count are added.
+    pub fn changes(&self, model: &ModelState) -> Vec<Change> {
+        let mut planner = Planner {
+            bundle: self,
+            model,
+            changes: vec![],
+            charms: IndexMap::new(),
+            deploys: IndexMap::new(),
+            machines: IndexMap::new(),
+            units: IndexMap::new(),
+        };
+
+        planner.handle_applications();
+        planner.handle_machines();
+        planner.handle_relations();
+        planner.handle_units();
+
+        planner.changes
+    }
+}
@@ -0,0 +1,513 @@
+//! Parsing for constraints such as `cores=2 mem=4G`
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+const MEBIBYTE: u64 = 1024 * 1024;
+
+/// Size suffixes understood by Juju, as multiples of a mebibyte
+const SIZE_SUFFIXES: &[(char, u64)] = &[
+    ('M', 1),
+    ('G', 1024),
+    ('T', 1024 * 1024),
+    ('P', 1024 * 1024 * 1024),
+];
+
+/// Parses a size such as `512M`, `4G` or `1.5T` into a byte count
+///
+/// As with Juju, sizes with no suffix are interpreted as mebibytes, and sizes are
+/// rounded up to a whole number of mebibytes, so `1.3G` is `1332M`.
+pub(crate) fn parse_size(input: &str) -> Result<u64, String> {
+    let (number, multiplier) = match input.chars().last() {
+        Some(ch) if ch.is_ascii_alphabetic() => {
+            let multiplier = SIZE_SUFFIXES
+                .iter()
+                .find(|(suffix, _)| *suffix == ch)
+                .map(|(_, m)| *m)
+                .ok_or_else(|| format!("Unknown size suffix `{}` in `{}`", ch, input))?;
+
+            (&input[..input.len() - 1], multiplier)
+        }
+        _ => (input, 1),
+    };
+
+    let number: f64 = number
+        .parse()
+        .map_err(|err| format!("Couldn't parse size `{}`: {}", input, err))?;
+
+    if !number.is_finite() || number < 0.0 {
+        return Err(format!("Size must be a positive number: `{}`", input));
+    }
+
+    Ok((number * multiplier as f64).ceil() as u64 * MEBIBYTE)
+}
+
+/// Formats a byte count using the largest suffix that represents it exactly
+///
+/// Byte counts that aren't a whole number of mebibytes are rounded up.
+pub(crate) fn format_size(bytes: u64) -> String {
+    let mebibytes = bytes.div_ceil(MEBIBYTE);
+
+    SIZE_SUFFIXES
+        .iter()
+        .rev()
+        .find(|(_, m)| mebibytes >= *m && mebibytes.is_multiple_of(*m))
+        .map(|(suffix, m)| format!("{}{}", mebibytes / m, suffix))
+        .unwrap_or_else(|| format!("{}M", mebibytes))
+}
+
+/// The constraints that Juju understands, in the order that they're written
+const KEYS: &[&str] = &[
+    "arch",
+    "container",
+    "cores",
+    "cpu-power",
+    "instance-type",
+    "instance-role",
+    "mem",
+    "root-disk",
+    "root-disk-source",
+    "tags",
+    "spaces",
+    "virt-type",
+    "zones",
+    "allocate-public-ip",
+    "image-id",
+];
+
+/// Constraints for the machines that an application is deployed to
+///
+/// See the [constraints documentation][constraints] for more info
+///
+/// [constraints]: https://juju.is/docs/olm/constraints
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Constraints {
+    /// Architecture, such as `amd64` or `arm64`
+    pub arch: Option<String>,
+
+    /// Type of container to deploy to, such as `lxd`
+    pub container: Option<String>,
+
+    /// Number of effective CPU cores
+    pub cores: Option<u64>,
+
+    /// Abstract CPU power, where 100 is roughly one core of a modern CPU
+    pub cpu_power: Option<u64>,
+
+    /// Cloud-specific instance type, such as `m5.large`
+    pub instance_type: Option<String>,
+
+    /// Instance profile to give the machine, or `auto` to create one, on AWS
+    pub instance_role: Option<String>,
+
+    /// Memory, in bytes
+    pub mem: Option<u64>,
+
+    /// Root disk size, in bytes
+    pub root_disk: Option<u64>,
+
+    /// Storage pool or volume type for the root disk
+    pub root_disk_source: Option<String>,
+
+    /// Tags that the machine must have, or must not have if prefixed with `^`
+    pub tags: Option<Vec<String>>,
+
+    /// Spaces that the machine must be in, or must not be in if prefixed with `^`
+    pub spaces: Option<Vec<String>>,
+
+    /// Virtualization type, such as `kvm`
+    pub virt_type: Option<String>,
+
+    /// Availability zones that the machine may be placed in
+    pub zones: Option<Vec<String>>,
+
+    /// Whether to allocate a public IP address
+    pub allocate_public_ip: Option<bool>,
+
+    /// Cloud-specific image to use for the machine
+    pub image_id: Option<String>,
+
+    /// Constraints given without a value, such as `mem=`
+    ///
+    /// Juju takes these to mean that the constraint is explicitly unset, which
+    /// overrides a value set elsewhere, e.g. in the model's constraints.
+    pub unset: Vec<String>,
+}
+
+impl Constraints {
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let mut constraints = Constraints::default();
+        let mut seen = vec![];
+
+        for pair in input.split_whitespace() {
+            let (key, value) = match pair.find('=') {
+                Some(i) => (&pair[..i], &pair[i + 1..]),
+                None => return Err(format!("Constraint `{}` is missing a value", pair)),
+            };
+
+            let key = match key {
+                "cpu-cores" => "cores",
+                k => k,
+            };
+
+            if seen.contains(&key) {
+                return Err(format!("Constraint `{}` specified more than once", key));
+            }
+            seen.push(key);
+
+            // An empty value explicitly leaves the constraint unset
+            if value.is_empty() {
+                if !KEYS.contains(&key) {
+                    return Err(format!("Unknown constraint `{}`", key));
+                }
+
+                constraints.unset.push(key.into());
+                continue;
+            }
+
+            let string = || Some(value.to_string());
+            let list = || Some(value.split(',').map(String::from).collect());
+            let number = || {
+                value
+                    .parse()
+                    .map(Some)
+                    .map_err(|err| format!("Couldn't parse constraint `{}`: {}", pair, err))
+            };
+
+            match key {
+                "arch" => constraints.arch = string(),
+                "container" => constraints.container = string(),
+                "cores" => constraints.cores = number()?,
+                "cpu-power" => constraints.cpu_power = number()?,
+                "instance-type" => constraints.instance_type = string(),
+                "instance-role" => constraints.instance_role = string(),
+                "mem" => constraints.mem = Some(parse_size(value)?),
+                "root-disk" => constraints.root_disk = Some(parse_size(value)?),
+                "root-disk-source" => constraints.root_disk_source = string(),
+                "tags" => constraints.tags = list(),
+                "spaces" => constraints.spaces = list(),
+                "virt-type" => constraints.virt_type = string(),
+                "zones" => constraints.zones = list(),
+                "allocate-public-ip" => {
+                    constraints.allocate_public_ip =
+                        Some(value.parse().map_err(|err| {
+                            format!("Couldn't parse constraint `{}`: {}", pair, err)
+                        })?)
+                }
+                "image-id" => constraints.image_id = string(),
+                _ => return Err(format!("Unknown constraint `{}`", key)),
+            }
+        }
+
+        Ok(constraints)
+    }
+
+    /// Whether any constraints are set
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// Returns these constraints, with any constraints set in `other` taking precedence
+    ///
+    /// Constraints that `other` explicitly unsets are unset in the result too.
+    pub fn merge(&self, other: &Constraints) -> Constraints {
+        let keep = |key: &str| !other.unset.iter().any(|k| k == key);
+
+        let mut merged = Constraints {
+            arch: other
+                .arch
+                .clone()
+                .or_else(|| self.arch.clone().filter(|_| keep("arch"))),
+            container: other
+                .container
+                .clone()
+                .or_else(|| self.container.clone().filter(|_| keep("container"))),
+            cores: other.cores.or(self.cores.filter(|_| keep("cores"))),
+            cpu_power: other
+                .cpu_power
+                .or(self.cpu_power.filter(|_| keep("cpu-power"))),
+            instance_type: other
+                .instance_type
+                .clone()
+                .or_else(|| self.instance_type.clone().filter(|_| keep("instance-type"))),
+            instance_role: other
+                .instance_role
+                .clone()
+                .or_else(|| self.instance_role.clone().filter(|_| keep("instance-role"))),
+            mem: other.mem.or(self.mem.filter(|_| keep("mem"))),
+            root_disk: other
+                .root_disk
+                .or(self.root_disk.filter(|_| keep("root-disk"))),
+            root_disk_source: other.root_disk_source.clone().or_else(|| {
+                self.root_disk_source
+                    .clone()
+                    .filter(|_| keep("root-disk-source"))
+            }),
+            tags: other
+                .tags
+                .clone()
+                .or_else(|| self.tags.clone().filter(|_| keep("tags"))),
+            spaces: other
+                .spaces
+                .clone()
+                .or_else(|| self.spaces.clone().filter(|_| keep("spaces"))),
+            virt_type: other
+                .virt_type
+                .clone()
+                .or_else(|| self.virt_type.clone().filter(|_| keep("virt-type"))),
+            zones: other
+                .zones
+                .clone()
+                .or_else(|| self.zones.clone().filter(|_| keep("zones"))),
+            allocate_public_ip: other.allocate_public_ip.or(self
+                .allocate_public_ip
+                .filter(|_| keep("allocate-public-ip"))),
+            image_id: other
+                .image_id
+                .clone()
+                .or_else(|| self.image_id.clone().filter(|_| keep("image-id"))),
+            unset: vec![],
+        };
+
+        // Keys stay explicitly unset unless the other side gives them a value
+        let values = merged.pairs();
+        for key in KEYS {
+            let unset = self.unset.iter().chain(&other.unset).any(|k| k == key);
+            let valued = values.iter().any(|(k, v)| k == key && v.is_some());
+
+            if unset && !valued {
+                merged.unset.push(key.to_string());
+            }
+        }
+
+        merged
+    }
+
+    /// Each constraint's key and value, in the order that they're written
+    fn pairs(&self) -> Vec<(&'static str, Option<String>)> {
+        vec![
+            ("arch", self.arch.clone()),
+            ("container", self.container.clone()),
+            ("cores", self.cores.map(|c| c.to_string())),
+            ("cpu-power", self.cpu_power.map(|c| c.to_string())),
+            ("instance-type", self.instance_type.clone()),
+            ("instance-role", self.instance_role.clone()),
+            ("mem", self.mem.map(format_size)),
+            ("root-disk", self.root_disk.map(format_size)),
+            ("root-disk-source", self.root_disk_source.clone()),
+            ("tags", self.tags.as_ref().map(|t| t.join(","))),
+            ("spaces", self.spaces.as_ref().map(|s| s.join(","))),
+            ("virt-type", self.virt_type.clone()),
+            ("zones", self.zones.as_ref().map(|z| z.join(","))),
+            (
+                "allocate-public-ip",
+                self.allocate_public_ip.map(|a| a.to_string()),
+            ),
+            ("image-id", self.image_id.clone()),
+        ]
+    }
+}
+
+impl FromStr for Constraints {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl TryFrom<&str> for Constraints {
+    type Error = String;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for Constraints {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Explicitly unset constraints are written with an empty value, e.g. `mem=`
+        let serialized = self
+            .pairs()
+            .into_iter()
+            .filter_map(|(k, v)| match v {
+                Some(v) => Some(format!("{}={}", k, v)),
+                None if self.unset.iter().any(|u| u == k) => Some(format!("{}=", k)),
+                None => None,
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        f.write_str(&serialized)
+    }
+}
+
+impl Serialize for Constraints {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Constraints {
+    fn deserialize<D>(deserializer: D) -> Result<Constraints, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        use serde::de::Error;
+        let s = String::deserialize(deserializer)?;
+
+        s.parse()
+            .map_err(|err| Error::custom(format!("Error deserializing Constraints: {}", err)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_yaml::{from_str, to_string};
+
+    use super::*;
+
+    #[test]
+    fn test_size() {
+        assert_eq!(parse_size("512").unwrap(), 512 * MEBIBYTE);
+        assert_eq!(parse_size("512M").unwrap(), 512 * MEBIBYTE);
+        assert_eq!(parse_size("4G").unwrap(), 4096 * MEBIBYTE);
+        assert_eq!(parse_size("1.5G").unwrap(), 1536 * MEBIBYTE);
+        assert_eq!(parse_size("2T").unwrap(), 2 * 1024 * 1024 * MEBIBYTE);
+        assert!(parse_size("4X").is_err());
+        assert!(parse_size("G").is_err());
+        assert!(parse_size("-1G").is_err());
+
+        assert_eq!(format_size(512 * MEBIBYTE), "512M");
+        assert_eq!(format_size(1536 * MEBIBYTE), "1536M");
+        assert_eq!(format_size(4096 * MEBIBYTE), "4G");
+        assert_eq!(format_size(2 * 1024 * 1024 * MEBIBYTE), "2T");
+
+        // Fractional sizes are rounded up to the next mebibyte, as Juju does
+        assert_eq!(parse_size("1.3G").unwrap(), 1332 * MEBIBYTE);
+        assert_eq!(parse_size("0.5M").unwrap(), MEBIBYTE);
+        assert_eq!(parse_size("0.0001G").unwrap(), MEBIBYTE);
+        assert_eq!(format_size(parse_size("1.3G").unwrap()), "1332M");
+        assert_eq!(format_size(1), "1M");
+        assert_eq!(format_size(1536 * MEBIBYTE + 1), "1537M");
+        assert_eq!(format_size(0), "0M");
+    }
+
+    #[test]
+    fn test_full_valid() {
+        let parsed: Constraints = "cores=2 mem=4G root-disk=16G tags=foo,^bar arch=arm64 \
+                                   allocate-public-ip=true virt-type=kvm"
+            .parse()
+            .unwrap();
+
+        assert_eq!(
+            parsed,
+            Constraints {
+                arch: Some("arm64".into()),
+                cores: Some(2),
+                mem: Some(4096 * MEBIBYTE),
+                root_disk: Some(16384 * MEBIBYTE),
+                tags: Some(vec!["foo".into(), "^bar".into()]),
+                virt_type: Some("kvm".into()),
+                allocate_public_ip: Some(true),
+                ..Default::default()
+            }
+        );
+
+        assert_eq!(
+            parsed.to_string(),
+            "arch=arm64 cores=2 mem=4G root-disk=16G tags=foo,^bar virt-type=kvm \
+             allocate-public-ip=true"
+        );
+    }
+
+    #[test]
+    fn test_provider_specific() {
+        let input = "container=lxd instance-role=auto root-disk-source=volume";
+        let parsed: Constraints = input.parse().unwrap();
+
+        assert_eq!(parsed.container.as_deref(), Some("lxd"));
+        assert_eq!(parsed.instance_role.as_deref(), Some("auto"));
+        assert_eq!(parsed.root_disk_source.as_deref(), Some("volume"));
+        assert_eq!(parsed.to_string(), input);
+    }
+
+    #[test]
+    fn test_unset() {
+        let parsed: Constraints = "cores=2 mem= tags=".parse().unwrap();
+
+        assert_eq!(parsed.mem, None);
+        assert_eq!(parsed.unset, vec!["mem", "tags"]);
+        assert!(!parsed.is_empty());
+        assert_eq!(parsed.to_string(), "cores=2 mem= tags=");
+        assert_eq!(parsed.to_string().parse::<Constraints>().unwrap(), parsed);
+
+        assert!("memory=".parse::<Constraints>().is_err());
+    }
+
+    #[test]
+    fn test_alias() {
+        let parsed: Constraints = "cpu-cores=4".parse().unwrap();
+
+        assert_eq!(parsed.cores, Some(4));
+        assert_eq!(parsed.to_string(), "cores=4");
+    }
+
+    #[test]
+    fn test_invalid() {
+        let invalid = vec![
+            "cores",
+            "cores=two",
+            "mem=4Q",
+            "cores=1 cores=2",
+            "cores=1 cpu-cores=2",
+            "allocate-public-ip=yes",
+            "memory=4G",
+        ];
+
+        for constraints in invalid {
+            println!("Testing {}", constraints);
+            assert!(constraints.parse::<Constraints>().is_err());
+        }
+    }
+
+    #[test]
+    fn test_merge() {
+        let base: Constraints = "cores=2 mem=4G zones=a,b".parse().unwrap();
+        let other: Constraints = "mem=8G arch=amd64".parse().unwrap();
+
+        assert_eq!(
+            base.merge(&other).to_string(),
+            "arch=amd64 cores=2 mem=8G zones=a,b"
+        );
+
+        // Explicitly unsetting a constraint overrides the base's value
+        let unset: Constraints = "zones= arch=".parse().unwrap();
+        assert_eq!(
+            base.merge(&unset).to_string(),
+            "arch= cores=2 mem=4G zones="
+        );
+        assert_eq!(unset.merge(&other).to_string(), "arch=amd64 mem=8G zones=");
+    }
+
+    #[test]
+    fn test_serialization() {
+        let constraints: Constraints = "mem=2048M cores=2".parse().unwrap();
+
+        let serialized = "---\ncores=2 mem=2G\n";
+
+        assert_eq!(&to_string(&constraints).unwrap()[..], serialized);
+
+        let parsed: Constraints = from_str(serialized).unwrap();
+        assert_eq!(parsed, constraints);
+
+        let constraints: Constraints = "mem=1.3G root-disk=0.5M".parse().unwrap();
+        assert_eq!(constraints.to_string(), "mem=1332M root-disk=1M");
+    }
+}
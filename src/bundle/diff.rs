@@ -0,0 +1,243 @@
+//! Semantic diffs between two bundles
+
+use std::fmt;
+
+use indexmap::IndexMap;
+
+use super::{Application, Bundle, BundleRelation, Constraints, Value};
+use crate::charm_url::CharmURL;
+
+/// A value that differs between two bundles
+#[derive(Debug, Clone, PartialEq)]
+pub struct Changed<T> {
+    pub old: T,
+    pub new: T,
+}
+
+impl<T: PartialEq> Changed<T> {
+    /// Returns the change if the values differ
+    fn between(old: T, new: T) -> Option<Self> {
+        if old == new {
+            None
+        } else {
+            Some(Self { old, new })
+        }
+    }
+}
+
+/// Changes to a single application that exists in both bundles
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ApplicationDiff {
+    /// The charm URL, including any change in revision
+    pub charm: Option<Changed<Option<CharmURL>>>,
+
+    pub channel: Option<Changed<Option<String>>>,
+
+    pub scale: Option<Changed<u32>>,
+
+    pub constraints: Option<Changed<Option<Constraints>>>,
+
+    /// Changed config options, from both `options` and `config`
+    ///
+    /// An option that was added or removed is `None` on the side it's missing from.
+    pub options: IndexMap<String, Changed<Option<Value>>>,
+
+    /// Changed resources, with `None` on the side a resource is missing from
+    pub resources: IndexMap<String, Changed<Option<String>>>,
+}
+
+impl ApplicationDiff {
+    fn new(old: &Application, new: &Application) -> Self {
+        Self {
+            charm: Changed::between(old.charm.clone(), new.charm.clone()),
+            channel: Changed::between(old.channel.clone(), new.channel.clone()),
+            scale: Changed::between(old.scale, new.scale),
+            constraints: Changed::between(old.constraints.clone(), new.constraints.clone()),
+            options: diff_maps(&all_options(old), &all_options(new)),
+            resources: diff_maps(&old.resources, &new.resources),
+        }
+    }
+
+    /// Whether nothing changed
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+/// Combines an application's `options` and `config`, which Juju treats the same
+fn all_options(app: &Application) -> IndexMap<String, Value> {
+    app.options
+        .iter()
+        .chain(app.config.iter())
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect()
+}
+
+/// Lists the keys whose values differ between two maps
+fn diff_maps<T: Clone + PartialEq>(
+    old: &IndexMap<String, T>,
+    new: &IndexMap<String, T>,
+) -> IndexMap<String, Changed<Option<T>>> {
+    old.keys()
+        .chain(new.keys().filter(|k| !old.contains_key(*k)))
+        .filter_map(|k| {
+            Changed::between(old.get(k).cloned(), new.get(k).cloned()).map(|c| (k.clone(), c))
+        })
+        .collect()
+}
+
+/// The differences between two bundles
+///
+/// Created with `Bundle::diff`. The `Display` implementation prints a report
+/// suitable for reviewing what a bundle change will actually do.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct BundleDiff {
+    pub added_applications: Vec<String>,
+    pub removed_applications: Vec<String>,
+    pub changed_applications: IndexMap<String, ApplicationDiff>,
+    pub added_relations: Vec<BundleRelation>,
+    pub removed_relations: Vec<BundleRelation>,
+}
+
+impl BundleDiff {
+    /// Whether the bundles are equivalent
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+impl Bundle {
+    /// Compares this bundle with `other`, treating `other` as the newer version
+    pub fn diff(&self, other: &Bundle) -> BundleDiff {
+        let added_applications = other
+            .applications
+            .keys()
+            .filter(|k| !self.applications.contains_key(*k))
+            .cloned()
+            .collect();
+
+        let removed_applications = self
+            .applications
+            .keys()
+            .filter(|k| !other.applications.contains_key(*k))
+            .cloned()
+            .collect();
+
+        let changed_applications = self
+            .applications
+            .iter()
+            .filter_map(|(name, old)| {
+                let new = other.applications.get(name)?;
+                let diff = ApplicationDiff::new(old, new);
+
+                if diff.is_empty() {
+                    None
+                } else {
+                    Some((name.clone(), diff))
+                }
+            })
+            .collect();
+
+        let missing_from = |relations: &[BundleRelation], from: &[BundleRelation]| {
+            relations
+                .iter()
+                .filter(|r| !from.iter().any(|f| f.is_equivalent(r)))
+                .cloned()
+                .collect()
+        };
+
+        BundleDiff {
+            added_applications,
+            removed_applications,
+            changed_applications,
+            added_relations: missing_from(&other.relations, &self.relations),
+            removed_relations: missing_from(&self.relations, &other.relations),
+        }
+    }
+}
+
+/// Formats an optional value, showing `None` as `(unset)`
+fn or_unset<T: fmt::Display>(value: &Option<T>) -> String {
+    match value {
+        Some(v) => v.to_string(),
+        None => "(unset)".into(),
+    }
+}
+
+fn write_change<T: fmt::Display>(
+    f: &mut fmt::Formatter<'_>,
+    field: &str,
+    change: &Changed<Option<T>>,
+) -> fmt::Result {
+    writeln!(
+        f,
+        "    {}: {} -> {}",
+        field,
+        or_unset(&change.old),
+        or_unset(&change.new)
+    )
+}
+
+impl fmt::Display for BundleDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "No changes");
+        }
+
+        if !self.added_applications.is_empty() {
+            writeln!(f, "Added applications:")?;
+            for name in &self.added_applications {
+                writeln!(f, "  + {}", name)?;
+            }
+        }
+
+        if !self.removed_applications.is_empty() {
+            writeln!(f, "Removed applications:")?;
+            for name in &self.removed_applications {
+                writeln!(f, "  - {}", name)?;
+            }
+        }
+
+        if !self.changed_applications.is_empty() {
+            writeln!(f, "Changed applications:")?;
+            for (name, diff) in &self.changed_applications {
+                writeln!(f, "  {}:", name)?;
+
+                if let Some(change) = &diff.charm {
+                    write_change(f, "charm", change)?;
+                }
+                if let Some(change) = &diff.channel {
+                    write_change(f, "channel", change)?;
+                }
+                if let Some(change) = &diff.scale {
+                    writeln!(f, "    scale: {} -> {}", change.old, change.new)?;
+                }
+                if let Some(change) = &diff.constraints {
+                    write_change(f, "constraints", change)?;
+                }
+                for (option, change) in &diff.options {
+                    write_change(f, &format!("option {}", option), change)?;
+                }
+                for (resource, change) in &diff.resources {
+                    write_change(f, &format!("resource {}", resource), change)?;
+                }
+            }
+        }
+
+        if !self.added_relations.is_empty() {
+            writeln!(f, "Added relations:")?;
+            for relation in &self.added_relations {
+                writeln!(f, "  + {}", relation)?;
+            }
+        }
+
+        if !self.removed_relations.is_empty() {
+            writeln!(f, "Removed relations:")?;
+            for relation in &self.removed_relations {
+                writeln!(f, "  - {}", relation)?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,457 @@
+//! Parsing for storage and device directives such as `ebs,10G,3` or `2,nvidia.com/gpu`
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use super::constraints::{format_size, parse_size};
+use crate::charm_source::{Device, Storage};
+
+/// Whether a storage directive field is a valid pool name
+///
+/// Pool names must start with a letter, followed by letters, numbers or dashes.
+fn is_pool_name(field: &str) -> bool {
+    let mut chars = field.chars();
+
+    chars
+        .next()
+        .map(|ch| ch.is_ascii_alphabetic())
+        .unwrap_or(false)
+        && chars.all(|ch| ch.is_ascii_alphanumeric() || ch == '-')
+}
+
+/// Parses a charm's `minimum-size`, such as `1G`, `1GiB` or `1GB`
+fn parse_minimum_size(size: &str) -> Result<u64, String> {
+    parse_size(size.trim_end_matches("iB").trim_end_matches('B'))
+}
+
+/// Parses a `multiple` range, such as `3`, `1-10`, `2-` or `2+`
+fn parse_range(range: &str) -> Result<(u32, Option<u32>), String> {
+    let err = |err: std::num::ParseIntError| format!("Couldn't parse range `{}`: {}", range, err);
+
+    if let Some(min) = range.strip_suffix('+').or_else(|| range.strip_suffix('-')) {
+        Ok((min.parse().map_err(err)?, None))
+    } else if let Some(i) = range.find('-') {
+        Ok((
+            range[..i].parse().map_err(err)?,
+            Some(range[i + 1..].parse().map_err(err)?),
+        ))
+    } else {
+        let count = range.parse().map_err(err)?;
+        Ok((count, Some(count)))
+    }
+}
+
+/// Storage to attach to each unit of an application
+///
+/// Each field is optional, and takes the form `[<pool>][,<size>][,<count>]`. Sizes require a
+/// suffix such as `G`, as plain numbers are interpreted as counts.
+///
+/// See the [storage documentation][storage] for more info
+///
+/// [storage]: https://juju.is/docs/olm/defining-and-using-persistent-storage
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct StorageDirective {
+    /// Storage pool to allocate from, such as `ebs` or `rootfs`
+    pub pool: Option<String>,
+
+    /// Size of each storage instance, in bytes
+    pub size: Option<u64>,
+
+    /// Number of storage instances to attach
+    pub count: Option<u32>,
+}
+
+impl StorageDirective {
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let mut directive = StorageDirective::default();
+
+        for field in input.split(',').filter(|f| !f.is_empty()) {
+            if is_pool_name(field) {
+                if directive.pool.is_some() {
+                    return Err(format!(
+                        "Storage pool specified more than once in `{}`",
+                        input
+                    ));
+                }
+                directive.pool = Some(field.into());
+            } else if let Ok(count) = field.parse() {
+                if directive.count.is_some() {
+                    return Err(format!(
+                        "Storage count specified more than once in `{}`",
+                        input
+                    ));
+                }
+                directive.count = Some(count);
+            } else {
+                if directive.size.is_some() {
+                    return Err(format!(
+                        "Storage size specified more than once in `{}`",
+                        input
+                    ));
+                }
+                directive.size = Some(parse_size(field)?);
+            }
+        }
+
+        Ok(directive)
+    }
+
+    /// Checks this directive against the storage that the charm declares
+    pub fn check(&self, storage: &Storage) -> Result<(), String> {
+        let (multiple, minimum_size) = match storage {
+            Storage::Filesystem {
+                multiple,
+                minimum_size,
+                ..
+            }
+            | Storage::Block {
+                multiple,
+                minimum_size,
+                ..
+            } => (multiple, minimum_size),
+        };
+
+        if let Some(count) = self.count {
+            let (min, max) = match multiple {
+                Some(range) => parse_range(range)?,
+                None => (1, Some(1)),
+            };
+
+            if count < min || max.map(|max| count > max).unwrap_or(false) {
+                return Err(format!(
+                    "Storage count {} is outside of the range allowed by the charm",
+                    count
+                ));
+            }
+        }
+
+        if let (Some(size), Some(minimum)) = (self.size, minimum_size) {
+            if size < parse_minimum_size(minimum)? {
+                return Err(format!(
+                    "Storage size {} is smaller than the charm's minimum of {}",
+                    format_size(size),
+                    minimum
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for StorageDirective {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl TryFrom<&str> for StorageDirective {
+    type Error = String;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for StorageDirective {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fields = vec![
+            self.pool.clone(),
+            self.size.map(format_size),
+            self.count.map(|c| c.to_string()),
+        ];
+
+        let serialized = fields.into_iter().flatten().collect::<Vec<_>>().join(",");
+
+        f.write_str(&serialized)
+    }
+}
+
+impl Serialize for StorageDirective {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for StorageDirective {
+    fn deserialize<D>(deserializer: D) -> Result<StorageDirective, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        use serde::de::Error;
+        let s = String::deserialize(deserializer)?;
+
+        s.parse()
+            .map_err(|err| Error::custom(format!("Error deserializing StorageDirective: {}", err)))
+    }
+}
+
+/// Devices to attach to each unit of an application, such as GPUs
+///
+/// Takes the form `[<count>,]<type>[,<key>=<value>;...]`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceDirective {
+    /// The type of device, such as `gpu` or `nvidia.com/gpu`
+    pub kind: String,
+
+    /// Number of devices to attach
+    pub count: u32,
+
+    /// Attributes used to select a device, such as `gpu=nvidia-tesla-p100`
+    pub attributes: HashMap<String, String>,
+}
+
+impl DeviceDirective {
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let mut kind = None;
+        let mut count = None;
+        let mut attributes = HashMap::new();
+
+        for field in input.split(',') {
+            if field.contains('=') {
+                for attr in field.split(';').filter(|a| !a.is_empty()) {
+                    let (k, v) = match attr.find('=') {
+                        Some(i) => (&attr[..i], &attr[i + 1..]),
+                        None => return Err(format!("Device attribute `{}` has no value", attr)),
+                    };
+                    attributes.insert(k.to_string(), v.to_string());
+                }
+            } else if let Ok(c) = field.parse::<u32>() {
+                if c == 0 || count.replace(c).is_some() {
+                    return Err(format!("Invalid device count in `{}`", input));
+                }
+            } else if field.is_empty() || kind.is_some() {
+                return Err(format!("Invalid device type in `{}`", input));
+            } else {
+                kind = Some(field.to_string());
+            }
+        }
+
+        Ok(Self {
+            kind: kind.ok_or_else(|| format!("Device type missing from `{}`", input))?,
+            count: count.unwrap_or(1),
+            attributes,
+        })
+    }
+
+    /// Checks this directive against the device that the charm declares
+    pub fn check(&self, device: &Device) -> Result<(), String> {
+        if self.kind != device.kind {
+            return Err(format!(
+                "Device type `{}` doesn't match the charm's type `{}`",
+                self.kind, device.kind
+            ));
+        }
+
+        let too_few = device.countmin.map(|min| self.count < min).unwrap_or(false);
+        let too_many = device.countmax.map(|max| self.count > max).unwrap_or(false);
+
+        if too_few || too_many {
+            return Err(format!(
+                "Device count {} is outside of the range allowed by the charm",
+                self.count
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for DeviceDirective {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl TryFrom<&str> for DeviceDirective {
+    type Error = String;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for DeviceDirective {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.count != 1 {
+            write!(f, "{},", self.count)?;
+        }
+
+        f.write_str(&self.kind)?;
+
+        if !self.attributes.is_empty() {
+            let mut attributes = self
+                .attributes
+                .iter()
+                .map(|(k, v)| format!("{}={}", k, v))
+                .collect::<Vec<_>>();
+            attributes.sort();
+
+            write!(f, ",{}", attributes.join(";"))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Serialize for DeviceDirective {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for DeviceDirective {
+    fn deserialize<D>(deserializer: D) -> Result<DeviceDirective, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        use serde::de::Error;
+        let s = String::deserialize(deserializer)?;
+
+        s.parse()
+            .map_err(|err| Error::custom(format!("Error deserializing DeviceDirective: {}", err)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GIBIBYTE: u64 = 1024 * 1024 * 1024;
+
+    #[test]
+    fn test_storage() {
+        let directives = vec!["ebs,10G,3", "ebs", "10G", "3", "3,10G", "ebs-ssd,2"];
+
+        let expecteds = vec![
+            StorageDirective {
+                pool: Some("ebs".into()),
+                size: Some(10 * GIBIBYTE),
+                count: Some(3),
+            },
+            StorageDirective {
+                pool: Some("ebs".into()),
+                ..Default::default()
+            },
+            StorageDirective {
+                size: Some(10 * GIBIBYTE),
+                ..Default::default()
+            },
+            StorageDirective {
+                count: Some(3),
+                ..Default::default()
+            },
+            StorageDirective {
+                size: Some(10 * GIBIBYTE),
+                count: Some(3),
+                ..Default::default()
+            },
+            StorageDirective {
+                pool: Some("ebs-ssd".into()),
+                count: Some(2),
+                ..Default::default()
+            },
+        ];
+
+        for (directive, expected) in directives.into_iter().zip(expecteds) {
+            println!("Testing {}", directive);
+            let parsed: StorageDirective = directive.parse().unwrap();
+            assert_eq!(parsed, expected);
+        }
+
+        assert_eq!(
+            "3,ebs,10G".parse::<StorageDirective>().unwrap().to_string(),
+            "ebs,10G,3"
+        );
+    }
+
+    #[test]
+    fn test_storage_invalid() {
+        for directive in &["ebs,gp2", "1,2", "10G,20G", "10Q"] {
+            println!("Testing {}", directive);
+            assert!(directive.parse::<StorageDirective>().is_err());
+        }
+    }
+
+    #[test]
+    fn test_storage_check() {
+        let storage = Storage::Block {
+            description: None,
+            location: None,
+            shared: false,
+            read_only: false,
+            multiple: Some("1-3".into()),
+            minimum_size: Some("1GiB".into()),
+            properties: vec![],
+        };
+
+        let check = |d: &str| StorageDirective::parse(d).unwrap().check(&storage);
+
+        assert!(check("ebs,10G,3").is_ok());
+        assert!(check("ebs").is_ok());
+        assert!(check("4").is_err());
+        assert!(check("512M").is_err());
+    }
+
+    #[test]
+    fn test_device() {
+        let parsed: DeviceDirective = "2,nvidia.com/gpu,gpu=tesla;mem=16G".parse().unwrap();
+
+        assert_eq!(
+            parsed,
+            DeviceDirective {
+                kind: "nvidia.com/gpu".into(),
+                count: 2,
+                attributes: [
+                    ("gpu".to_string(), "tesla".to_string()),
+                    ("mem".to_string(), "16G".to_string()),
+                ]
+                .into(),
+            }
+        );
+        assert_eq!(parsed.to_string(), "2,nvidia.com/gpu,gpu=tesla;mem=16G");
+
+        let parsed: DeviceDirective = "nvidia.com/gpu,1".parse().unwrap();
+        assert_eq!(parsed.count, 1);
+        assert_eq!(parsed.to_string(), "nvidia.com/gpu");
+    }
+
+    #[test]
+    fn test_device_invalid() {
+        for directive in &["", "2", "0,gpu", "gpu,tpu", "1,2,gpu", "gpu,foo=bar;baz"] {
+            println!("Testing {}", directive);
+            assert!(directive.parse::<DeviceDirective>().is_err());
+        }
+    }
+
+    #[test]
+    fn test_device_check() {
+        let device = Device {
+            kind: "gpu".into(),
+            description: None,
+            countmin: Some(1),
+            countmax: Some(2),
+        };
+
+        let check = |d: &str| DeviceDirective::parse(d).unwrap().check(&device);
+
+        assert!(check("gpu").is_ok());
+        assert!(check("2,gpu").is_ok());
+        assert!(check("3,gpu").is_err());
+        assert!(check("tpu").is_err());
+    }
+}
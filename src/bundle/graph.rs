@@ -0,0 +1,213 @@
+//! Graphs of the applications in a bundle and the relations between them
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use super::relation::{lookup_endpoint, matching_endpoints};
+use super::{Bundle, BundleRelation};
+use crate::charm_source::CharmSource;
+
+/// What kind of application a node represents
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeKind {
+    /// A principal application deployed by the bundle
+    Application,
+
+    /// A subordinate application, deployed alongside its principals
+    Subordinate,
+
+    /// An application consumed from another model
+    Saas,
+}
+
+/// An application in the graph
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Node {
+    pub name: String,
+    pub kind: NodeKind,
+}
+
+/// A relation between two applications
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edge {
+    pub left: String,
+    pub right: String,
+
+    /// The interface of the relation, if it could be worked out from the charms
+    pub interface: Option<String>,
+}
+
+/// The applications in a bundle and the relations between them
+///
+/// Created with `Bundle::graph`, and exported with `to_dot` or `to_mermaid`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Graph {
+    pub nodes: Vec<Node>,
+    pub edges: Vec<Edge>,
+}
+
+/// Works out a relation's interface from the charms on either side, if possible
+fn interface(relation: &BundleRelation, charms: &HashMap<String, CharmSource>) -> Option<String> {
+    let (left, right) = (&relation.left, &relation.right);
+    let left_charm = charms.get(&left.application).map(|c| &c.metadata);
+    let right_charm = charms.get(&right.application).map(|c| &c.metadata);
+
+    if let (Some(l), Some(r)) = (left_charm, right_charm) {
+        let matches = matching_endpoints(l, left.endpoint.as_deref(), r, right.endpoint.as_deref());
+
+        if let [(name, _)] = &matches[..] {
+            return lookup_endpoint(l, name).map(|(_, i)| i.to_string());
+        }
+    }
+
+    // Fall back to whichever side names an endpoint, e.g. for relations to SAAS entries
+    [(left_charm, left), (right_charm, right)]
+        .iter()
+        .find_map(|(charm, endpoint)| lookup_endpoint(charm.as_ref()?, endpoint.endpoint.as_ref()?))
+        .map(|(_, i)| i.to_string())
+}
+
+/// Escapes a string for use within double quotes in DOT
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Escapes a string for use within double quotes in Mermaid, which uses entity codes
+fn escape_mermaid(s: &str) -> String {
+    s.replace('#', "#35;").replace('"', "#quot;")
+}
+
+impl Graph {
+    /// Renders the graph in Graphviz DOT format
+    ///
+    /// Subordinates are drawn with dashed outlines, and SAAS entries as boxes.
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("graph bundle {\n");
+
+        for node in &self.nodes {
+            let attrs = match node.kind {
+                NodeKind::Application => "",
+                NodeKind::Subordinate => " [style=dashed]",
+                NodeKind::Saas => " [shape=box]",
+            };
+            writeln!(out, "    \"{}\"{};", escape(&node.name), attrs).unwrap();
+        }
+
+        for edge in &self.edges {
+            write!(
+                out,
+                "    \"{}\" -- \"{}\"",
+                escape(&edge.left),
+                escape(&edge.right)
+            )
+            .unwrap();
+            if let Some(interface) = &edge.interface {
+                write!(out, " [label=\"{}\"]", escape(interface)).unwrap();
+            }
+            out.push_str(";\n");
+        }
+
+        out.push_str("}\n");
+        out
+    }
+
+    /// Renders the graph as a Mermaid flowchart
+    ///
+    /// Subordinates are drawn as rounded nodes, and SAAS entries as subroutines.
+    pub fn to_mermaid(&self) -> String {
+        // Node IDs are generated, as application names aren't always valid Mermaid IDs
+        let ids: HashMap<&str, String> = self
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(i, node)| (node.name.as_str(), format!("n{}", i)))
+            .collect();
+
+        let mut out = String::from("graph LR\n");
+
+        for node in &self.nodes {
+            let (open, close) = match node.kind {
+                NodeKind::Application => ("[", "]"),
+                NodeKind::Subordinate => ("([", "])"),
+                NodeKind::Saas => ("[[", "]]"),
+            };
+            writeln!(
+                out,
+                "    {}{}\"{}\"{}",
+                ids[node.name.as_str()],
+                open,
+                escape_mermaid(&node.name),
+                close
+            )
+            .unwrap();
+        }
+
+        for edge in &self.edges {
+            let (left, right) = match (ids.get(edge.left.as_str()), ids.get(edge.right.as_str())) {
+                (Some(l), Some(r)) => (l, r),
+                _ => continue,
+            };
+
+            match &edge.interface {
+                Some(interface) => {
+                    writeln!(
+                        out,
+                        "    {} ---|\"{}\"| {}",
+                        left,
+                        escape_mermaid(interface),
+                        right
+                    )
+                }
+                None => writeln!(out, "    {} --- {}", left, right),
+            }
+            .unwrap();
+        }
+
+        out
+    }
+}
+
+impl Bundle {
+    /// Builds a graph of the bundle's applications and relations
+    ///
+    /// `charms` maps application names to their charm, and is used to label relations
+    /// with their interface and to mark subordinates. Applications without an entry
+    /// are still included, but their relations may be unlabelled.
+    pub fn graph(&self, charms: &HashMap<String, CharmSource>) -> Graph {
+        let applications = self.applications.keys().map(|name| {
+            let subordinate = charms
+                .get(name)
+                .map(|c| c.metadata.subordinate)
+                .unwrap_or(false);
+
+            Node {
+                name: name.clone(),
+                kind: if subordinate {
+                    NodeKind::Subordinate
+                } else {
+                    NodeKind::Application
+                },
+            }
+        });
+
+        let saas = self.saas.keys().map(|name| Node {
+            name: name.clone(),
+            kind: NodeKind::Saas,
+        });
+
+        let edges = self
+            .relations
+            .iter()
+            .map(|relation| Edge {
+                left: relation.left.application.clone(),
+                right: relation.right.application.clone(),
+                interface: interface(relation, charms),
+            })
+            .collect();
+
+        Graph {
+            nodes: applications.chain(saas).collect(),
+            edges,
+        }
+    }
+}
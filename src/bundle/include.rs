@@ -0,0 +1,80 @@
+//! Resolution of `include-file://` and `include-base64://` option values
+
+use std::fs::read;
+use std::path::{Path, PathBuf};
+
+use super::{Application, Bundle, Value};
+use crate::error::JujuError;
+
+const INCLUDE_FILE: &str = "include-file://";
+const INCLUDE_BASE64: &str = "include-base64://";
+
+/// Reads the file that an option value refers to, if it's an include directive
+///
+/// Returns `None` for values that aren't includes.
+fn resolve_value(
+    value: &str,
+    root: &Path,
+    name: &str,
+    option: &str,
+) -> Option<Result<String, JujuError>> {
+    let (path, encode) = if let Some(path) = value.strip_prefix(INCLUDE_FILE) {
+        (path, false)
+    } else if let Some(path) = value.strip_prefix(INCLUDE_BASE64) {
+        (path, true)
+    } else {
+        return None;
+    };
+
+    let include_error = |err| JujuError::IncludeError(name.into(), option.into(), path.into(), err);
+
+    let contents = match read(root.join(path)) {
+        Ok(c) => c,
+        Err(err) => return Some(Err(include_error(err))),
+    };
+
+    if encode {
+        return Some(Ok(base64::encode(contents)));
+    }
+
+    Some(
+        String::from_utf8(contents).map_err(|err| {
+            include_error(std::io::Error::new(std::io::ErrorKind::InvalidData, err))
+        }),
+    )
+}
+
+impl Application {
+    /// Inlines the contents of files referenced by `include-file://` and
+    /// `include-base64://` option values
+    ///
+    /// Relative paths are resolved relative to `bundle.yaml`, and files included
+    /// with `include-base64://` are base64-encoded.
+    pub fn resolve_includes(&mut self, name: &str, bundle_path: &str) -> Result<(), JujuError> {
+        let root = PathBuf::from(bundle_path);
+        let root = root.parent().unwrap();
+
+        for (option, value) in self.options.iter_mut().chain(self.config.iter_mut()) {
+            if let Value::String(s) = value {
+                if let Some(contents) = resolve_value(s, root, name, option) {
+                    *s = contents?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Bundle {
+    /// Inlines the contents of files referenced by option values in each application
+    ///
+    /// See `Application::resolve_includes` for details.
+    pub fn resolve_includes(&mut self, bundle_path: &str) -> Result<(), JujuError> {
+        for (name, app) in self.applications.iter_mut() {
+            app.resolve_includes(name, bundle_path)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,375 @@
+//! Lock files pinning a bundle to exact charm and resource revisions
+//!
+//! A `bundle.lock` lives next to `bundle.yaml`, and can be applied to the bundle
+//! later with `Bundle::apply_lock` to deploy exactly the same charms and resources
+//! again. Charms from a store are pinned to the revisions deployed in a model, see
+//! `Bundle::lock_model`, and charms built locally to a hash of their source.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use ex::fs::{read, write};
+use indexmap::IndexMap;
+use serde_derive::{Deserialize, Serialize};
+use serde_yaml::{from_slice, to_vec};
+
+use super::{source_path, Application, Bundle};
+use crate::charm_source::{CharmSource, Resource};
+use crate::charm_url::CharmURL;
+use crate::cmd;
+use crate::error::JujuError;
+use crate::local::ModelRef;
+use crate::status::{ApplicationStatus, ResourcesStatus, Status};
+
+/// The name of the lock file, which lives next to the bundle
+const LOCK_FILE: &str = "bundle.lock";
+
+/// The pinned charm and resources for a single application
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub struct LockedApplication {
+    /// The charm, without a revision
+    ///
+    /// Unset for charms built locally, which are rebuilt from `source` instead.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub charm: Option<CharmURL>,
+
+    /// The channel the charm was released to or deployed from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel: Option<String>,
+
+    /// The charm's revision, if it came from a store
+    ///
+    /// Kept apart from `charm`, as Juju 3 rejects revisions in Charmhub charm URLs.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revision: Option<u32>,
+
+    /// Resource revisions, or OCI images pinned by digest
+    #[serde(default)]
+    #[serde(skip_serializing_if = "IndexMap::is_empty")]
+    pub resources: IndexMap<String, String>,
+
+    /// The charm's source, relative to the bundle, for charms built locally
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+
+    /// Hash of the charm's source tree, for charms built locally
+    ///
+    /// See `CharmSource::source_hash`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_hash: Option<String>,
+}
+
+impl LockedApplication {
+    /// Records an application's charm and resources as they're currently set
+    ///
+    /// Nothing is resolved, so a charm without a revision stays unpinned. Returns
+    /// `None` if the application doesn't have a charm set.
+    pub fn new(app: &Application) -> Option<Self> {
+        Some(Self {
+            charm: Some(app.charm.clone()?),
+            channel: app.channel.clone(),
+            revision: app.revision,
+            resources: app.resources.clone(),
+            source: None,
+            source_hash: None,
+        })
+    }
+
+    /// Records an application as it's deployed in a model
+    ///
+    /// The charm is pinned to the revision that's running, and resources from the
+    /// store to the revisions in use. Uploaded resources are recorded as they're
+    /// set in the bundle. Charms deployed from a local path can't be pinned by
+    /// revision, so they're recorded as they're set in the bundle too.
+    pub fn deployed(
+        app: &Application,
+        status: &ApplicationStatus,
+        resources: &ResourcesStatus,
+    ) -> Option<Self> {
+        let mut locked = Self::new(app)?;
+
+        let local =
+            status.charm_origin.as_deref() == Some("local") || status.charm.starts_with("local:");
+
+        if local {
+            return Some(locked);
+        }
+
+        if status.charm_rev.is_some() {
+            locked.revision = status.charm_rev;
+        }
+
+        if status.charm_channel.is_some() {
+            locked.channel = status.charm_channel.clone();
+        }
+
+        for resource in &resources.resources {
+            if let (Some("store"), Some(revision)) =
+                (resource.origin.as_deref(), &resource.revision)
+            {
+                locked
+                    .resources
+                    .insert(resource.name.clone(), revision.clone());
+            }
+        }
+
+        Some(locked)
+    }
+
+    /// Records an application that was built from a local charm
+    ///
+    /// `source` is where the charm was built from, which is recorded relative to
+    /// the bundle at `bundle_path` so that the lock can be used on other machines.
+    /// OCI image resources are pinned to the digest of the locally pulled image, if
+    /// there is one. Images that aren't available locally are recorded as-is.
+    pub(crate) fn built(
+        app: &Application,
+        charm: &CharmSource,
+        source: &str,
+        bundle_path: &str,
+    ) -> Result<Self, JujuError> {
+        let mut resources = app.resources.clone();
+
+        for (name, value) in &mut resources {
+            if let Some(Resource::OciImage { .. }) = charm.metadata.resources.get(name) {
+                if let Some(digest) = image_digest(value) {
+                    *value = digest;
+                }
+            }
+        }
+
+        Ok(Self {
+            charm: None,
+            channel: None,
+            revision: None,
+            resources,
+            source: Some(relative_source(source, bundle_path)),
+            source_hash: Some(charm.source_hash()?),
+        })
+    }
+
+    /// Records an application whose charm was built locally and uploaded to Charmhub
+    ///
+    /// The charm and resources are pinned to the revisions they were uploaded as, and
+    /// the source is recorded as for `LockedApplication::built`.
+    pub(crate) fn uploaded(
+        app: &Application,
+        charm: &CharmSource,
+        source: &str,
+        bundle_path: &str,
+    ) -> Result<Self, JujuError> {
+        Ok(Self {
+            charm: app.charm.clone(),
+            channel: app.channel.clone(),
+            revision: app.revision,
+            resources: app.resources.clone(),
+            source: Some(relative_source(source, bundle_path)),
+            source_hash: Some(charm.source_hash()?),
+        })
+    }
+}
+
+/// Makes a charm source path relative to the bundle, if it's within the bundle's directory
+fn relative_source(source: &str, bundle_path: &str) -> String {
+    let bundle_dir = Path::new(bundle_path)
+        .parent()
+        .unwrap_or_else(|| Path::new(""));
+
+    if source.starts_with('.') || bundle_dir.as_os_str().is_empty() {
+        return source.into();
+    }
+
+    match Path::new(source).strip_prefix(bundle_dir) {
+        Ok(relative) => format!("./{}", relative.to_string_lossy()),
+        Err(_) => source.into(),
+    }
+}
+
+/// Looks up the digest of a locally pulled OCI image, e.g. `foo@sha256:...`
+fn image_digest(image: &str) -> Option<String> {
+    if image.contains('@') {
+        return Some(image.into());
+    }
+
+    let output = cmd::get_output(
+        "docker",
+        &[
+            "image",
+            "inspect",
+            "--format",
+            "{{index .RepoDigests 0}}",
+            image,
+        ],
+    )
+    .ok()?;
+    let digest = String::from_utf8_lossy(&output).trim().to_string();
+
+    if digest.contains('@') {
+        Some(digest)
+    } else {
+        None
+    }
+}
+
+/// Represents a `bundle.lock` file
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub struct BundleLock {
+    /// The pinned applications, keyed by name
+    #[serde(default)]
+    pub applications: IndexMap<String, LockedApplication>,
+}
+
+impl BundleLock {
+    /// The path of the lock file for the bundle at `bundle_path`
+    pub fn path_for<P: AsRef<Path>>(bundle_path: P) -> PathBuf {
+        bundle_path
+            .as_ref()
+            .parent()
+            .unwrap_or_else(|| Path::new(""))
+            .join(LOCK_FILE)
+    }
+
+    /// Load a lock file from the given path
+    pub fn load<P: Into<PathBuf>>(path: P) -> Result<Self, JujuError> {
+        Ok(from_slice(&read(path.into())?)?)
+    }
+
+    /// Save this lock file to the given path
+    pub fn save<P: Into<PathBuf>>(&self, path: P) -> Result<(), JujuError> {
+        write(path.into(), to_vec(self)?)?;
+        Ok(())
+    }
+}
+
+impl Bundle {
+    /// Records the charms and resources that the bundle's applications are set to use
+    ///
+    /// Applications without a charm set are left out. Nothing is resolved, so use
+    /// `Bundle::lock_model` to pin charms from a store to exact revisions. Charms
+    /// built locally are only recorded by `Bundle::build_with` and
+    /// `Bundle::upload_charms`.
+    pub fn lock(&self) -> BundleLock {
+        BundleLock {
+            applications: self
+                .applications
+                .iter()
+                .filter_map(|(name, app)| Some((name.clone(), LockedApplication::new(app)?)))
+                .collect(),
+        }
+    }
+
+    /// Records the charm and resource revisions that the bundle's applications are
+    /// deployed with, according to `status` and each application's `resources`
+    ///
+    /// Applications that aren't deployed are recorded as they're set in the bundle.
+    /// See `LockedApplication::deployed`.
+    pub fn lock_status(
+        &self,
+        status: &Status,
+        resources: &HashMap<String, ResourcesStatus>,
+    ) -> BundleLock {
+        let empty = ResourcesStatus::default();
+
+        BundleLock {
+            applications: self
+                .applications
+                .iter()
+                .filter_map(|(name, app)| {
+                    let locked = match status.applications.get(name) {
+                        Some(deployed) => LockedApplication::deployed(
+                            app,
+                            deployed,
+                            resources.get(name).unwrap_or(&empty),
+                        ),
+                        None => LockedApplication::new(app),
+                    };
+
+                    Some((name.clone(), locked?))
+                })
+                .collect(),
+        }
+    }
+
+    /// Records the charm and resource revisions that the bundle is deployed with in a model
+    ///
+    /// See `Bundle::lock_status`.
+    pub fn lock_model(&self, model: &ModelRef) -> Result<BundleLock, JujuError> {
+        let model_name = model.to_string();
+        let status = Status::current(Some(&model_name))?;
+
+        let mut resources = HashMap::new();
+        for name in self.applications.keys() {
+            if status.applications.contains_key(name) {
+                resources.insert(
+                    name.clone(),
+                    ResourcesStatus::current(Some(&model_name), name)?,
+                );
+            }
+        }
+
+        Ok(self.lock_status(&status, &resources))
+    }
+
+    /// Writes `bundle.lock` next to the bundle at `bundle_path`
+    ///
+    /// The charms and resources are recorded as they're set, see `Bundle::lock`.
+    pub fn write_lock(&self, bundle_path: &str) -> Result<(), JujuError> {
+        self.lock().save(BundleLock::path_for(bundle_path))
+    }
+
+    /// Pins the bundle's applications to the charms and resources in `lock`
+    ///
+    /// Applications that were built locally are pointed back at their source, so that
+    /// `Bundle::build` builds them again, and ones uploaded to Charmhub at the revision
+    /// they were uploaded as. Either way, the source must still hash to the same value
+    /// as when the lock was written, otherwise `JujuError::LockedSourceChanged` is
+    /// returned and the bundle is left unchanged. `bundle_path` is the path to the
+    /// bundle, which relative sources are resolved against. Applications that aren't
+    /// in the lock are left as they are, and locked applications that aren't in the
+    /// bundle are ignored.
+    pub fn apply_lock(&mut self, lock: &BundleLock, bundle_path: &str) -> Result<(), JujuError> {
+        let locked: Vec<_> = lock
+            .applications
+            .iter()
+            .filter(|(name, _)| self.applications.contains_key(*name))
+            .collect();
+
+        // Check every source before changing anything, so a mismatch doesn't leave
+        // the bundle partly pinned
+        for (name, locked) in &locked {
+            if let (Some(source), Some(expected)) = (&locked.source, &locked.source_hash) {
+                let charm = CharmSource::load(source_path(source, bundle_path))?;
+                let actual = charm.source_hash()?;
+
+                if &actual != expected {
+                    return Err(JujuError::LockedSourceChanged(
+                        (*name).clone(),
+                        expected.clone(),
+                        actual,
+                    ));
+                }
+            }
+        }
+
+        for (name, locked) in locked {
+            let app = &mut self.applications[name];
+
+            if let Some(charm) = &locked.charm {
+                app.charm = Some(charm.clone());
+                app.channel = locked.channel.clone();
+                app.revision = locked.revision;
+            }
+
+            // Charms that were only built locally are built again from source
+            if let (None, Some(source)) = (&locked.charm, &locked.source) {
+                app.charm = None;
+                app.source = Some(source.clone());
+            }
+
+            app.resources.extend(locked.resources.clone());
+        }
+
+        Ok(())
+    }
+}
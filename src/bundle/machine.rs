@@ -0,0 +1,48 @@
+use indexmap::IndexMap;
+use serde::{Deserialize, Deserializer};
+use serde_derive::{Deserialize, Serialize};
+
+use super::{Annotations, Constraints};
+
+/// A machine within a bundle
+///
+/// See the `MachineSpec` defined [here][spec] for the canonical upstream definition
+///
+/// [spec]: https://github.com/juju/charm/blob/master/bundledata.go
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub struct Machine {
+    /// Arbitrary annotations intepreted by things other than Juju itself
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Annotations::is_empty")]
+    pub annotations: Annotations,
+
+    /// Base to use for the machine, such as `ubuntu@22.04`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub base: Option<String>,
+
+    /// Constraints such as `cores=2 mem=4G`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub constraints: Option<Constraints>,
+
+    /// Series to use for the machine
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub series: Option<String>,
+}
+
+/// Deserializes the `machines` section of a bundle
+///
+/// Juju allows machines to be declared with no body at all, e.g. `"0":`, so
+/// null values are treated as a machine with all of the defaults.
+pub(crate) fn deserialize_machines<'de, D>(
+    deserializer: D,
+) -> Result<IndexMap<String, Machine>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let machines = IndexMap::<String, Option<Machine>>::deserialize(deserializer)?;
+
+    Ok(machines
+        .into_iter()
+        .map(|(id, machine)| (id, machine.unwrap_or_default()))
+        .collect())
+}
@@ -0,0 +1,664 @@
+//! Parsing for bundle.yaml files
+
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+
+use ex::fs::{canonicalize, read, write};
+use indexmap::IndexMap;
+use serde_derive::{Deserialize, Serialize};
+use serde_yaml::{from_value, to_value, to_vec};
+
+use crate::charm_source::{CharmSource, Metadata, Upload};
+use crate::charm_url::CharmURL;
+use crate::cmd;
+use crate::error::JujuError;
+use crate::paths;
+use crate::series::Series;
+
+pub use self::annotations::Annotations;
+pub use self::build::{BuildEvent, BuildFailure, BuildOptions, BuildReport, ProgressFn};
+pub use self::changes::{Change, DeployedApplication, EntityType, ModelState, Step};
+pub use self::constraints::Constraints;
+pub use self::diff::{ApplicationDiff, BundleDiff, Changed};
+pub use self::directive::{DeviceDirective, StorageDirective};
+pub use self::graph::{Edge, Graph, Node, NodeKind};
+pub use self::lock::{BundleLock, LockedApplication};
+pub use self::machine::Machine;
+pub use self::offer::Offer;
+pub use self::placement::{ContainerType, Placement};
+pub use self::relation::{BundleRelation, RelationEndpoint};
+pub use self::remove::RemoveOptions;
+pub use self::saas::Saas;
+pub use self::validate::{Diagnostic, Severity};
+pub use self::value::Value;
+
+pub mod annotations;
+pub mod build;
+pub mod changes;
+pub mod constraints;
+pub mod diff;
+pub mod directive;
+pub mod graph;
+mod include;
+pub mod lock;
+pub mod machine;
+pub mod offer;
+mod overlay;
+pub mod placement;
+mod preserve;
+pub mod relation;
+pub mod remove;
+pub mod saas;
+pub mod script;
+pub mod validate;
+pub mod value;
+
+/// An application within a bundle
+///
+/// See the `ApplicationSpec` defined [here][spec] for the canonical upstream definition
+///
+/// [spec]: https://github.com/juju/charm/blob/master/bundledata.go
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+pub struct Application {
+    /// Arbitrary annotations intepreted by things other than Juju itself
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Annotations::is_empty")]
+    pub annotations: Annotations,
+
+    /// Preferred channel to use when deploying a remote charm
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel: Option<String>,
+
+    /// URL of the charm
+    ///
+    /// Normally points to a charm store location in the form of `cs:~user/charm`
+    /// If not set, `Application::source` will be used to build the charm. If both
+    /// are set, this property is preferred, unless `--build` is passed. One or
+    /// the other must be set.
+    pub charm: Option<CharmURL>,
+
+    /// Used to set charm config at deployment time
+    ///
+    /// Duplicate of `options`, but Juju doesn't care if both are specified,
+    /// which serde doesn't like. So, we copy it here as well.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "IndexMap::is_empty")]
+    pub config: IndexMap<String, Value>,
+
+    /// Constraints such as `cores=2 mem=4G`
+    ///
+    /// See the [constraints documentation][constraints] for more info
+    ///
+    /// [constraints]: https://juju.is/docs/olm/constraints
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub constraints: Option<Constraints>,
+
+    /// Constraints for devices to assign to units of the application
+    #[serde(default)]
+    #[serde(skip_serializing_if = "IndexMap::is_empty")]
+    pub devices: IndexMap<String, DeviceDirective>,
+
+    /// Maps how endpoints are bound to spaces
+    #[serde(default)]
+    #[serde(skip_serializing_if = "IndexMap::is_empty")]
+    pub endpoint_bindings: IndexMap<String, String>,
+
+    /// Whether to expose the application externally
+    #[serde(default)]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub expose: bool,
+
+    /// Endpoints offered to other models, keyed by offer name
+    #[serde(default)]
+    #[serde(skip_serializing_if = "IndexMap::is_empty")]
+    pub offers: IndexMap<String, Offer>,
+
+    /// Used to set charm config at deployment time
+    #[serde(default)]
+    #[serde(skip_serializing_if = "IndexMap::is_empty")]
+    pub options: IndexMap<String, Value>,
+
+    /// Model selector/affinity expression for specifying pod placement
+    ///
+    /// Use for Kubernetes applications, not relevant for IaaS applications
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub placement: Option<String>,
+
+    /// Plan under which the application is to be deployed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub plan: Option<String>,
+
+    /// Whether the application requires access to cloud credentials
+    #[serde(default)]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub trust: bool,
+
+    /// Resources to make available to the application
+    ///
+    /// See <https://juju.is/docs/sdk/resources> for more info
+    #[serde(default)]
+    #[serde(skip_serializing_if = "IndexMap::is_empty")]
+    pub resources: IndexMap<String, String>,
+
+    /// Revision of the charm to deploy from `channel`
+    ///
+    /// Charmhub charms are pinned with this rather than a revision in `charm`,
+    /// which Juju 3 rejects.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revision: Option<u32>,
+
+    /// How many units to use for the application
+    #[serde(default, alias = "num_units")]
+    #[serde(skip_serializing_if = "is_zero")]
+    pub scale: u32,
+
+    /// Series to use when deploying a local charm
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub series: Option<String>,
+
+    /// Charm source code location
+    ///
+    /// If the path starts with `.`, it's interpreted as being relative to
+    /// the bundle itself. Otherwise, it's interpreted as being relative to
+    /// `$CHARM_SOURCE_DIR`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+
+    /// Constraints for storage to assign to units of the application
+    #[serde(default)]
+    #[serde(skip_serializing_if = "IndexMap::is_empty")]
+    pub storage: IndexMap<String, StorageDirective>,
+
+    /// Which Node (Kubernetes) or Unit (IaaS) this charm should be assigned to
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub to: Vec<Placement>,
+}
+
+fn is_zero(n: &u32) -> bool {
+    *n == 0
+}
+
+/// Resolves an application's `source` to the charm's source directory
+///
+/// If `source` starts with `.`, it's a relative path from the bundle we're
+/// deploying. Otherwise, look in `CHARM_SOURCE_DIR` for it.
+pub(crate) fn source_path(source: &str, bundle_path: &str) -> PathBuf {
+    if source.starts_with('.') {
+        PathBuf::from(bundle_path).parent().unwrap().join(source)
+    } else {
+        paths::charm_source_dir().join(source)
+    }
+}
+
+impl Application {
+    /// The placement directive for the unit with the given index, if it has one
+    ///
+    /// As in Juju, a last directive that places units alongside another application,
+    /// such as `foo`, carries on for the units past the end of `to`.
+    pub fn placement(&self, index: u32) -> Option<&Placement> {
+        match self.to.get(index as usize) {
+            Some(placement) => Some(placement),
+            None => match self.to.last()? {
+                last @ Placement::Unit { unit: None, .. } => Some(last),
+                _ => None,
+            },
+        }
+    }
+
+    pub fn upgrade(&self, name: &str) -> Result<(), JujuError> {
+        let source_dir = self
+            .charm
+            .as_ref()
+            .map(ToString::to_string)
+            .expect("Built charm directory can't be empty");
+        let charm = CharmSource::load(PathBuf::from(&source_dir))?;
+        let resources = charm.resources_with_defaults(&self.resources)?;
+
+        let args = vec!["upgrade-charm", name, "--path", &source_dir]
+            .into_iter()
+            .map(String::from)
+            .chain(script::resource_args(&resources))
+            .collect::<Vec<_>>();
+
+        cmd::run("juju", &args)
+    }
+
+    /// Checks storage and device directives against the charm's metadata
+    ///
+    /// Catches typos in storage and device names, as well as counts and sizes
+    /// that the charm doesn't allow.
+    pub fn validate_directives(&self, name: &str, metadata: &Metadata) -> Result<(), JujuError> {
+        match self.directive_errors(metadata).into_iter().next() {
+            Some(err) => Err(JujuError::InvalidDirective(name.into(), err)),
+            None => Ok(()),
+        }
+    }
+
+    /// Lists any problems with storage and device directives
+    fn directive_errors(&self, metadata: &Metadata) -> Vec<String> {
+        let storage = self.storage.iter().filter_map(|(name, directive)| {
+            match metadata.storage.get(name) {
+                Some(storage) => directive.check(storage).err(),
+                None => Some(format!("Charm has no storage named `{}`", name)),
+            }
+            .map(|err| format!("{}: {}", name, err))
+        });
+
+        let devices = self.devices.iter().filter_map(|(name, directive)| {
+            match metadata.devices.get(name) {
+                Some(device) => directive.check(device).err(),
+                None => Some(format!("Charm has no device named `{}`", name)),
+            }
+            .map(|err| format!("{}: {}", name, err))
+        });
+
+        let mut errors: Vec<_> = storage.chain(devices).collect();
+        errors.sort();
+        errors
+    }
+
+    /// Calculates the path to the charm's source directory
+    ///
+    /// This can be either manually set with `source: ./foo` in `bundle.yaml`,
+    /// or implicit in the existence of a `./charms/foo` directory, relative
+    /// to `bundle.yaml`.
+    pub fn source(&self, name: &str, bundle_path: &str) -> Option<String> {
+        match &self.source {
+            Some(s) => Some(s.clone()),
+            None => {
+                let root = PathBuf::from(bundle_path);
+                let root = root.parent().unwrap();
+                let paths = [
+                    root.join("./").join(name),
+                    root.join("./charms/").join(name),
+                    root.join("./operators/").join(name),
+                ];
+
+                for path in &paths {
+                    if path.exists() {
+                        return Some(path.to_string_lossy().to_string());
+                    }
+                }
+
+                None
+            }
+        }
+    }
+
+    pub fn upload_charmhub(
+        &self,
+        name: &str,
+        bundle_path: &str,
+        channels: &[String],
+        destructive_mode: bool,
+    ) -> Result<String, JujuError> {
+        let name = self
+            .charm
+            .as_ref()
+            .map(|c| c.name.as_ref())
+            .unwrap_or(name)
+            .to_string();
+        let source = self.source(&name, bundle_path);
+
+        match &source {
+            Some(source) => {
+                let charm = CharmSource::load(source_path(source, bundle_path))?;
+
+                let rev_url = charm.upload_charmhub(&self.resources, channels, destructive_mode)?;
+                Ok(rev_url)
+            }
+            None => {
+                unreachable!()
+            }
+        }
+    }
+}
+
+/// Represents a `bundle.yaml` file
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+pub struct Bundle {
+    /// Bundle name, used for uploading to charm store
+    #[serde(default)]
+    pub name: Option<String>,
+
+    /// Arbitrary annotations for the bundle as a whole
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Annotations::is_empty")]
+    pub annotations: Annotations,
+
+    /// The applications in the bundle
+    #[serde(default, alias = "services")]
+    pub applications: IndexMap<String, Application>,
+
+    /// Which OS series to use for this bundle
+    ///
+    /// Either this or `series` must be set
+    pub bundle: Option<Series>,
+
+    /// Long-form description of the bundle
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+
+    /// Machines to create for IaaS bundles, keyed by machine ID
+    ///
+    /// Referred to from `Application::to` with directives such as `0` or `lxd:1`
+    #[serde(default, deserialize_with = "machine::deserialize_machines")]
+    #[serde(skip_serializing_if = "IndexMap::is_empty")]
+    pub machines: IndexMap<String, Machine>,
+
+    /// Pairs of application names that require a relation between them
+    #[serde(default)]
+    pub relations: Vec<BundleRelation>,
+
+    /// Remote offers consumed by the bundle
+    ///
+    /// The keys can be used in `relations` in the same way as application names
+    #[serde(default)]
+    #[serde(skip_serializing_if = "IndexMap::is_empty")]
+    pub saas: IndexMap<String, Saas>,
+
+    /// Which OS series to use for this bundle
+    ///
+    /// Either this or `bundle` must be set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub series: Option<Series>,
+}
+
+impl Bundle {
+    /// Load a bundle from the given path
+    ///
+    /// If the file contains multiple YAML documents, the first one is treated
+    /// as the base bundle and the rest are applied to it as overlays.
+    pub fn load<P: Into<PathBuf>>(path: P) -> Result<Self, JujuError> {
+        Ok(from_value(overlay::load_documents(&read(path.into())?)?)?)
+    }
+
+    /// Load a bundle from the given path, and apply overlays from other files to it
+    ///
+    /// Equivalent to `juju deploy bundle.yaml --overlay foo.yaml --overlay bar.yaml`.
+    /// If an overlay lives in a different directory than the bundle, any relative
+    /// `source` paths within it are resolved relative to the overlay.
+    pub fn load_with_overlays<P: Into<PathBuf>, Q: Into<PathBuf> + Clone>(
+        path: P,
+        overlays: &[Q],
+    ) -> Result<Self, JujuError> {
+        let path = path.into();
+        let mut merged = overlay::load_documents(&read(&path)?)?;
+        let bundle_dir = canonicalize(path.parent().unwrap())?;
+
+        for overlay_path in overlays {
+            let overlay_path = overlay_path.clone().into();
+            let mut document = overlay::load_documents(&read(&overlay_path)?)?;
+            let overlay_dir = canonicalize(overlay_path.parent().unwrap())?;
+
+            if overlay_dir != bundle_dir {
+                overlay::resolve_sources(&mut document, &overlay_dir);
+            }
+
+            overlay::merge(&mut merged, document);
+        }
+
+        Ok(from_value(merged)?)
+    }
+
+    /// Apply an overlay to this bundle, following the same rules as `juju deploy --overlay`
+    ///
+    /// Applications are deep-merged, so that e.g. only the options set in the overlay
+    /// are changed, and relations are appended. Removing an application by setting it
+    /// to null can't be represented by a typed `Bundle`, so use `Bundle::load` or
+    /// `Bundle::load_with_overlays` for overlays that do that.
+    ///
+    /// Likewise, a typed overlay can't tell a field that's set to its default apart
+    /// from one that isn't set at all, so `expose: false`, `trust: false` and
+    /// `num_units: 0` in the overlay are ignored. Use `Bundle::apply_overlay_yaml`
+    /// for overlays that need to set those.
+    pub fn apply_overlay(&mut self, overlay: &Bundle) -> Result<(), JujuError> {
+        let mut merged = to_value(&*self)?;
+        overlay::merge(&mut merged, overlay::strip_nulls(to_value(overlay)?));
+        *self = from_value(merged)?;
+
+        Ok(())
+    }
+
+    /// Apply an overlay to this bundle from its YAML, following the same rules as
+    /// `juju deploy --overlay`
+    ///
+    /// Unlike `Bundle::apply_overlay`, every field written in the overlay is applied,
+    /// including applications set to null and fields set to their defaults. If the
+    /// YAML contains multiple documents, they're applied in order.
+    pub fn apply_overlay_yaml(&mut self, overlay: &str) -> Result<(), JujuError> {
+        let mut merged = to_value(&*self)?;
+
+        for document in overlay::documents(overlay.as_bytes())? {
+            overlay::merge(&mut merged, document);
+        }
+
+        *self = from_value(merged)?;
+
+        Ok(())
+    }
+
+    /// Save this bundle to the given path
+    pub fn save<P: Into<PathBuf>>(&self, path: P) -> Result<(), JujuError> {
+        write(path.into(), to_vec(self)?)?;
+        Ok(())
+    }
+
+    /// Save this bundle to the given path, keeping the layout and comments of the file there
+    ///
+    /// Sections and applications that haven't changed since the file was written
+    /// are kept exactly as they were, so that saving an unmodified bundle doesn't
+    /// change the file at all. If there's no file at the path yet, this is the
+    /// same as `Bundle::save`.
+    pub fn save_preserving<P: Into<PathBuf>>(&self, path: P) -> Result<(), JujuError> {
+        let path = path.into();
+
+        if !path.exists() {
+            return self.save(path);
+        }
+
+        let original = String::from_utf8_lossy(&read(&path)?).into_owned();
+        write(path, self.to_string_preserving(&original)?)?;
+        Ok(())
+    }
+
+    /// Serializes this bundle, keeping the layout and comments of `original` where possible
+    ///
+    /// See `Bundle::save_preserving` for details.
+    pub fn to_string_preserving(&self, original: &str) -> Result<String, JujuError> {
+        preserve::render(self, original)
+    }
+
+    /// Updates bundle to use subset of applications
+    pub fn limit_apps(&mut self, names: &[String], exceptions: &[String]) -> Result<(), JujuError> {
+        if names.is_empty() {
+            return Ok(());
+        }
+
+        self.applications
+            .retain(|k, _| names.contains(k) && !exceptions.contains(k));
+
+        // Filter out relations that point to an application that was filtered out.
+        // SAAS entries aren't filtered, so relations to them are kept.
+        let apps: HashSet<_> = self
+            .applications
+            .keys()
+            .chain(self.saas.keys())
+            .map(String::as_str)
+            .collect();
+        self.relations
+            .retain(|rel| rel.applications().iter().all(|app| apps.contains(app)));
+
+        Ok(())
+    }
+
+    /// Checks that placement directives refer to machines defined in the bundle
+    ///
+    /// Directives such as `new`, `lxd` or `foo/0` aren't machine IDs and are
+    /// passed through. Directives such as `0` or `lxd:1` must have a matching
+    /// entry in `machines`.
+    pub fn validate_placement(&self) -> Result<(), JujuError> {
+        for (name, app) in &self.applications {
+            for placement in &app.to {
+                if let Some(id) = placement.machine() {
+                    if !self.machines.contains_key(&id.to_string()) {
+                        return Err(JujuError::UnknownMachine(
+                            name.clone(),
+                            placement.to_string(),
+                        ));
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn upload_charmhub(&self, bundle_path: &str, channel: &str) -> Result<(), JujuError> {
+        let pack_output = cmd::get_output("charmcraft", &["pack", "-p", bundle_path])?;
+
+        // Look for filename of zipped bundle in command output. Surrounded by single quotes.
+        let path = std::str::from_utf8(&pack_output)
+            .unwrap()
+            .chars()
+            .skip_while(|&ch| ch != '\'')
+            .skip(1)
+            .take_while(|&ch| ch != '\'')
+            .collect::<String>();
+
+        let args = vec!["upload", &path, "--release", channel];
+
+        let output = cmd::get_output("charmcraft", &args)?;
+
+        println!("\n// https://github.com/canonical/charmcraft/issues/478");
+        println!("Output from charmcraft upload in case something broke:");
+        println!("{}", String::from_utf8_lossy(&output));
+
+        Ok(())
+    }
+
+    /// Uploads the charms that have a source to Charmhub, releasing them to `channels`
+    ///
+    /// Each application is pointed at the revisions that its charm and OCI image
+    /// resources were uploaded as. The charm revision is the one built for the
+    /// application's series and architecture, picked the same way as by
+    /// `Bundle::build_with`. `bundle.lock` is written next to the bundle at
+    /// `bundle_path` to pin them, along with a hash of the source they were built
+    /// from. Applications without a source are left as they are.
+    pub fn upload_charms(
+        &mut self,
+        bundle_path: &str,
+        channels: &[String],
+        destructive_mode: bool,
+    ) -> Result<BundleLock, JujuError> {
+        let mut lock = self.lock();
+        let mut uploads: HashMap<String, (CharmSource, Upload)> = HashMap::new();
+
+        let names: Vec<_> = self.applications.keys().cloned().collect();
+
+        for name in names {
+            let source = match self.applications[&name].source(&name, bundle_path) {
+                Some(source) => source,
+                None => continue,
+            };
+
+            // Applications sharing a source share an upload
+            if !uploads.contains_key(&source) {
+                let charm = CharmSource::load(source_path(&source, bundle_path))?;
+                let upload = charm.upload(
+                    &self.applications[&name].resources,
+                    channels,
+                    destructive_mode,
+                )?;
+                uploads.insert(source.clone(), (charm, upload));
+            }
+            let (charm, upload) = &uploads[&source];
+
+            let (series, arch) = self.target_platform(&self.applications[&name]);
+            let revision = upload
+                .revision(series.as_deref(), arch.as_deref())
+                .ok_or_else(|| {
+                    JujuError::NoMatchingArtifact(
+                        name.clone(),
+                        format!(
+                            "{}/{}",
+                            series.as_deref().unwrap_or("any series"),
+                            arch.as_deref().unwrap_or("any architecture")
+                        ),
+                    )
+                })?;
+
+            let app = &mut self.applications[&name];
+            app.charm = Some(upload.charm.clone());
+            app.revision = Some(revision);
+            if let Some(channel) = channels.first() {
+                app.channel = Some(channel.clone());
+            }
+            app.resources.extend(upload.resources.clone());
+
+            lock.applications.insert(
+                name.clone(),
+                LockedApplication::uploaded(app, charm, &source, bundle_path)?,
+            );
+        }
+
+        lock.save(BundleLock::path_for(bundle_path))?;
+
+        Ok(lock)
+    }
+
+    pub fn upgrade_charms(&self) -> Result<(), JujuError> {
+        for (name, app) in &self.applications {
+            app.upgrade(name)?;
+        }
+
+        Ok(())
+    }
+
+    /// Builds the bundle's charms from source
+    ///
+    /// See `Bundle::build_with`. Build output goes to the terminal when building one
+    /// charm at a time, and to log files under `paths::charm_build_dir` otherwise.
+    pub fn build(
+        &mut self,
+        path: &str,
+        build_apps: Option<HashMap<String, Option<String>>>,
+        destructive_mode: bool,
+        parallel_build: bool,
+    ) -> Result<(), JujuError> {
+        let opts = BuildOptions {
+            destructive_mode,
+            jobs: if parallel_build { None } else { Some(1) },
+            log_dir: if parallel_build {
+                Some(paths::charm_build_dir().join("logs"))
+            } else {
+                None
+            },
+            progress: Some(Box::new(|event| {
+                if let BuildEvent::Started(name) = event {
+                    println!("Building {}", name);
+                }
+            })),
+            keep_going: false,
+            write_lock: false,
+            native: false,
+            wheelhouse: None,
+        };
+
+        self.build_with(path, build_apps, &opts).map(|_| ())
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(untagged)]
+enum CharmStoreResponse {
+    Bundle(Box<Bundle>),
+    #[serde(rename_all = "PascalCase")]
+    Error {
+        code: String,
+        message: String,
+        info: serde_yaml::Value,
+    },
+}
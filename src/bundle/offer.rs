@@ -0,0 +1,20 @@
+use indexmap::IndexMap;
+
+use serde_derive::{Deserialize, Serialize};
+
+/// Endpoints of an application exposed to other models
+///
+/// See the `OfferSpec` defined [here][spec] for the canonical upstream definition
+///
+/// [spec]: https://github.com/juju/charm/blob/master/bundledata.go
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub struct Offer {
+    /// Endpoints of the application to include in the offer
+    #[serde(default)]
+    pub endpoints: Vec<String>,
+
+    /// Access levels granted to users, such as `read`, `consume` or `admin`
+    #[serde(default)]
+    #[serde(skip_serializing_if = "IndexMap::is_empty")]
+    pub acl: IndexMap<String, String>,
+}
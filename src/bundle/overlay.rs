@@ -0,0 +1,224 @@
+//! Merging of overlay bundles, as done by `juju deploy --overlay`
+//!
+//! Overlays are merged as raw YAML, since a typed `Bundle` can't represent an
+//! application that has been set to null in order to remove it.
+
+use std::path::Path;
+
+use serde::Deserialize;
+use serde_yaml::{Deserializer, Mapping, Value};
+
+use crate::error::JujuError;
+
+/// Top-level sections whose entries are merged individually instead of being replaced
+const MERGED_SECTIONS: &[&str] = &["applications", "machines", "saas"];
+
+/// Parses every document in a YAML stream and merges them together
+///
+/// The first document is the base bundle, and any others are applied to it as
+/// overlays in the order that they appear.
+pub(crate) fn load_documents(bytes: &[u8]) -> Result<Value, JujuError> {
+    let mut merged: Option<Value> = None;
+
+    for document in documents(bytes)? {
+        match &mut merged {
+            Some(base) => merge(base, document),
+            None => merged = Some(document),
+        }
+    }
+
+    Ok(merged.unwrap_or(Value::Null))
+}
+
+/// Parses and normalizes every document in a YAML stream, without merging them
+pub(crate) fn documents(bytes: &[u8]) -> Result<Vec<Value>, JujuError> {
+    Deserializer::from_slice(bytes)
+        .map(|document| Ok(normalize(Value::deserialize(document)?)))
+        .collect()
+}
+
+/// Normalizes a bundle document so that it can be merged with other documents
+///
+/// Renames the legacy `services` section to `applications` and the `num_units` field
+/// to `scale`, and makes sure that machine IDs are always strings, as `0` and `"0"`
+/// refer to the same machine.
+pub(crate) fn normalize(mut document: Value) -> Value {
+    if let Value::Mapping(doc) = &mut document {
+        if let Some(services) = doc.remove(&key("services")) {
+            doc.entry(key("applications")).or_insert(services);
+        }
+
+        if let Some(Value::Mapping(apps)) = doc.get_mut(&key("applications")) {
+            for (_, app) in apps.iter_mut() {
+                if let Value::Mapping(app) = app {
+                    if let Some(units) = app.remove(&key("num_units")) {
+                        app.insert(key("scale"), units);
+                    }
+                }
+            }
+        }
+
+        if let Some(Value::Mapping(machines)) = doc.get_mut(&key("machines")) {
+            *machines = std::mem::take(machines)
+                .into_iter()
+                .map(|(id, machine)| match id {
+                    Value::Number(n) => (Value::String(n.to_string()), machine),
+                    id => (id, machine),
+                })
+                .collect();
+        }
+    }
+
+    document
+}
+
+/// Merges an overlay into a base bundle
+///
+/// Follows the same rules as Juju:
+///
+///  - Applications, machines and SAAS entries are deep-merged with any
+///    existing entry of the same name, or removed if set to null
+///  - Relations are appended, and relations to removed applications dropped
+///  - Any other top-level field replaces the base field
+pub(crate) fn merge(base: &mut Value, overlay: Value) {
+    let (base, overlay) = match (base, overlay) {
+        (Value::Mapping(b), Value::Mapping(o)) => (b, o),
+        (base, overlay) => {
+            *base = overlay;
+            return;
+        }
+    };
+
+    let mut removed = vec![];
+
+    for (k, v) in overlay {
+        match k.as_str() {
+            Some("relations") => append_relations(base, v),
+            Some(section) if MERGED_SECTIONS.contains(&section) => {
+                if let Value::Mapping(entries) = v {
+                    let existing = base
+                        .entry(k.clone())
+                        .or_insert_with(|| Value::Mapping(Mapping::new()));
+
+                    if !existing.is_mapping() {
+                        *existing = Value::Mapping(Mapping::new());
+                    }
+
+                    for (name, entry) in entries {
+                        if entry.is_null() && section == "applications" {
+                            removed.extend(name.as_str().map(String::from));
+                        }
+                        deep_merge_entry(existing.as_mapping_mut().unwrap(), name, entry);
+                    }
+                }
+            }
+            _ => deep_merge_entry(base, k, v),
+        }
+    }
+
+    if !removed.is_empty() {
+        if let Some(Value::Sequence(relations)) = base.get_mut(&key("relations")) {
+            relations.retain(|rel| {
+                !relation_apps(rel)
+                    .iter()
+                    .any(|app| removed.iter().any(|r| r == app))
+            });
+        }
+    }
+}
+
+/// Makes relative `source` paths in an overlay absolute
+///
+/// Relative paths are otherwise resolved against the base bundle, which is wrong
+/// for overlays that live in a different directory.
+pub(crate) fn resolve_sources(document: &mut Value, overlay_dir: &Path) {
+    let apps = document
+        .as_mapping_mut()
+        .and_then(|doc| doc.get_mut(&key("applications")))
+        .and_then(Value::as_mapping_mut);
+
+    for (_, app) in apps.into_iter().flatten() {
+        let source = app
+            .as_mapping_mut()
+            .and_then(|app| app.get_mut(&key("source")));
+
+        if let Some(Value::String(source)) = source {
+            if source.starts_with('.') {
+                *source = overlay_dir.join(&source).to_string_lossy().to_string();
+            }
+        }
+    }
+}
+
+/// Recursively removes null values, as unset fields in a typed overlay shouldn't
+/// remove anything from the base bundle
+pub(crate) fn strip_nulls(value: Value) -> Value {
+    match value {
+        Value::Mapping(m) => Value::Mapping(
+            m.into_iter()
+                .filter(|(_, v)| !v.is_null())
+                .map(|(k, v)| (k, strip_nulls(v)))
+                .collect(),
+        ),
+        v => v,
+    }
+}
+
+/// Merges a single key into a mapping, removing it if the new value is null
+fn deep_merge_entry(base: &mut Mapping, k: Value, v: Value) {
+    match (base.get_mut(&k), v) {
+        // `Mapping::remove` swaps in the last entry, so filter instead to keep the order
+        (_, Value::Null) => {
+            *base = std::mem::take(base)
+                .into_iter()
+                .filter(|(existing, _)| existing != &k)
+                .collect();
+        }
+        (Some(Value::Mapping(existing)), Value::Mapping(overlay)) => {
+            for (k, v) in overlay {
+                deep_merge_entry(existing, k, v);
+            }
+        }
+        (_, v) => {
+            base.insert(k, v);
+        }
+    }
+}
+
+/// Appends relations that aren't already present in the base bundle
+fn append_relations(base: &mut Mapping, relations: Value) {
+    let relations = match relations {
+        Value::Sequence(r) => r,
+        _ => return,
+    };
+
+    let existing = base
+        .entry(key("relations"))
+        .or_insert_with(|| Value::Sequence(vec![]));
+
+    if let Value::Sequence(existing) = existing {
+        for rel in relations {
+            if !existing.contains(&rel) {
+                existing.push(rel);
+            }
+        }
+    }
+}
+
+/// Gets the application names from a relation, e.g. `[foo:bar, baz]` => `[foo, baz]`
+fn relation_apps(relation: &Value) -> Vec<&str> {
+    relation
+        .as_sequence()
+        .map(|endpoints| {
+            endpoints
+                .iter()
+                .filter_map(Value::as_str)
+                .map(|e| e.split(':').next().unwrap())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn key(k: &str) -> Value {
+    Value::String(k.into())
+}
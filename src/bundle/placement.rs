@@ -0,0 +1,383 @@
+//! Parsing for `to:` placement directives
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use nom::branch::alt;
+use nom::bytes::complete::{tag, take_while1};
+use nom::character::complete::digit1;
+use nom::combinator::{all_consuming, map, map_res, opt, rest, value, verify};
+use nom::sequence::{preceded, separated_pair, terminated, tuple};
+use nom::IResult;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Type of container that a unit can be placed in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ContainerType {
+    Lxd,
+    Kvm,
+}
+
+impl fmt::Display for ContainerType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ContainerType::Lxd => "lxd",
+            ContainerType::Kvm => "kvm",
+        })
+    }
+}
+
+/// Parses a container type, such as `lxd`
+fn parse_container_type(input: &str) -> IResult<&str, ContainerType> {
+    alt((
+        value(ContainerType::Lxd, tag("lxd")),
+        value(ContainerType::Kvm, tag("kvm")),
+    ))(input)
+}
+
+/// Parses a machine ID or unit number
+fn parse_number(input: &str) -> IResult<&str, u32> {
+    map_res(digit1, str::parse)(input)
+}
+
+/// Whether a name follows Juju's rules for application names
+///
+/// Names are lowercase letters and digits, split up by single dashes, and start
+/// with a letter. Each part between dashes needs at least one letter, so `foo-1a`
+/// is valid but `foo-1` isn't.
+fn is_application_name(name: &str) -> bool {
+    name.starts_with(|ch: char| ch.is_ascii_lowercase())
+        && name.split('-').all(|part| {
+            part.chars()
+                .all(|ch| ch.is_ascii_lowercase() || ch.is_ascii_digit())
+                && part.chars().any(|ch| ch.is_ascii_lowercase())
+        })
+}
+
+/// Parses an application name, such as `foo` or `foo-1a`
+fn parse_application(input: &str) -> IResult<&str, &str> {
+    verify(
+        take_while1(|ch: char| ch.is_ascii_lowercase() || ch.is_ascii_digit() || ch == '-'),
+        is_application_name,
+    )(input)
+}
+
+/// Parses an application with an optional unit number, such as `foo` or `foo/0`
+fn parse_unit(input: &str) -> IResult<&str, (&str, Option<u32>)> {
+    tuple((parse_application, opt(preceded(tag("/"), parse_number))))(input)
+}
+
+/// Parses a full placement directive
+fn parse_placement(input: &str) -> IResult<&str, Placement> {
+    alt((
+        map(
+            preceded(tag("zone="), verify(rest, |z: &str| !z.is_empty())),
+            |z: &str| Placement::Zone(z.into()),
+        ),
+        value(Placement::New, all_consuming(tag("new"))),
+        map(all_consuming(parse_number), Placement::Machine),
+        map(
+            all_consuming(terminated(parse_container_type, opt(tag(":new")))),
+            |kind| Placement::Container {
+                kind,
+                machine: None,
+            },
+        ),
+        map(
+            all_consuming(separated_pair(parse_container_type, tag(":"), parse_number)),
+            |(kind, machine)| Placement::Container {
+                kind,
+                machine: Some(machine),
+            },
+        ),
+        map(
+            all_consuming(tuple((
+                opt(terminated(parse_container_type, tag(":"))),
+                parse_unit,
+            ))),
+            |(container, (application, unit))| Placement::Unit {
+                container,
+                application: application.into(),
+                unit,
+            },
+        ),
+    ))(input)
+}
+
+/// A placement directive, as used in `Application::to`
+///
+/// See the [placement documentation][placement] for more info
+///
+/// [placement]: https://juju.is/docs/olm/bundle-reference
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Placement {
+    /// A machine defined in the bundle, such as `0`
+    Machine(u32),
+
+    /// A new machine, `new`
+    New,
+
+    /// A container on a machine defined in the bundle, such as `lxd:1`
+    ///
+    /// If `machine` isn't set, the container is created on a new machine, e.g. `lxd` or
+    /// `lxd:new`.
+    Container {
+        kind: ContainerType,
+        machine: Option<u32>,
+    },
+
+    /// Alongside a unit of another application, such as `foo/0` or `lxd:foo/0`
+    ///
+    /// If `unit` isn't set, units are placed round-robin alongside the application's units.
+    Unit {
+        container: Option<ContainerType>,
+        application: String,
+        unit: Option<u32>,
+    },
+
+    /// A new machine in the given availability zone, such as `zone=us-east-1a`
+    Zone(String),
+}
+
+impl Placement {
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let (_, placement) = parse_placement(input)
+            .map_err(|err| format!("Couldn't parse placement `{}`: {:?}", input, err))?;
+
+        Ok(placement)
+    }
+
+    /// Returns the bundle machine ID that this directive refers to, if any
+    pub fn machine(&self) -> Option<u32> {
+        match self {
+            Placement::Machine(id) => Some(*id),
+            Placement::Container { machine, .. } => *machine,
+            _ => None,
+        }
+    }
+
+    /// Returns the container type that this directive creates, if any
+    pub fn container(&self) -> Option<ContainerType> {
+        match self {
+            Placement::Container { kind, .. } => Some(*kind),
+            Placement::Unit { container, .. } => *container,
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for Placement {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl TryFrom<&str> for Placement {
+    type Error = String;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for Placement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Placement::Machine(id) => write!(f, "{}", id),
+            Placement::New => f.write_str("new"),
+            Placement::Container {
+                kind,
+                machine: Some(id),
+            } => write!(f, "{}:{}", kind, id),
+            // `lxd` and `lxd:new` mean the same thing, so write the shorter form
+            Placement::Container {
+                kind,
+                machine: None,
+            } => write!(f, "{}", kind),
+            Placement::Unit {
+                container,
+                application,
+                unit,
+            } => {
+                if let Some(c) = container {
+                    write!(f, "{}:", c)?;
+                }
+
+                f.write_str(application)?;
+
+                if let Some(u) = unit {
+                    write!(f, "/{}", u)?;
+                }
+
+                Ok(())
+            }
+            Placement::Zone(zone) => write!(f, "zone={}", zone),
+        }
+    }
+}
+
+impl Serialize for Placement {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Placement {
+    fn deserialize<D>(deserializer: D) -> Result<Placement, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        use serde::de::Error;
+        let s = String::deserialize(deserializer)?;
+
+        s.parse()
+            .map_err(|err| Error::custom(format!("Error deserializing Placement: {}", err)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_yaml::{from_str, to_string};
+
+    use super::*;
+
+    #[test]
+    fn test_container_type() {
+        let (remainder, parsed) = parse_container_type("lxd:1").unwrap();
+
+        assert_eq!(parsed, ContainerType::Lxd);
+        assert_eq!(remainder, ":1");
+    }
+
+    #[test]
+    fn test_unit() {
+        let (remainder, parsed) = parse_unit("foo-bar/2").unwrap();
+
+        assert_eq!(parsed, ("foo-bar", Some(2)));
+        assert_eq!(remainder, "");
+    }
+
+    #[test]
+    fn test_full_valid() {
+        let directives = vec![
+            "0",
+            "new",
+            "lxd",
+            "lxd:new",
+            "kvm:3",
+            "foo",
+            "foo/1",
+            "lxd:foo/1",
+            "lxd-foo",
+            "newt",
+            "foo-1a/0",
+            "zone=us-east-1a",
+        ];
+
+        let expecteds = vec![
+            Placement::Machine(0),
+            Placement::New,
+            Placement::Container {
+                kind: ContainerType::Lxd,
+                machine: None,
+            },
+            Placement::Container {
+                kind: ContainerType::Lxd,
+                machine: None,
+            },
+            Placement::Container {
+                kind: ContainerType::Kvm,
+                machine: Some(3),
+            },
+            Placement::Unit {
+                container: None,
+                application: "foo".into(),
+                unit: None,
+            },
+            Placement::Unit {
+                container: None,
+                application: "foo".into(),
+                unit: Some(1),
+            },
+            Placement::Unit {
+                container: Some(ContainerType::Lxd),
+                application: "foo".into(),
+                unit: Some(1),
+            },
+            Placement::Unit {
+                container: None,
+                application: "lxd-foo".into(),
+                unit: None,
+            },
+            Placement::Unit {
+                container: None,
+                application: "newt".into(),
+                unit: None,
+            },
+            Placement::Unit {
+                container: None,
+                application: "foo-1a".into(),
+                unit: Some(0),
+            },
+            Placement::Zone("us-east-1a".into()),
+        ];
+
+        for (directive, expected) in directives.into_iter().zip(expecteds) {
+            println!("Testing {}", directive);
+            let parsed: Placement = directive.parse().unwrap();
+            assert_eq!(parsed, expected);
+        }
+    }
+
+    #[test]
+    fn test_invalid() {
+        for directive in &[
+            "", "lxd:", "foo/", "foo/bar", "zone=", "docker:1", "foo-1", "foo--bar", "-foo", "Foo",
+        ] {
+            println!("Testing {}", directive);
+            assert!(directive.parse::<Placement>().is_err());
+        }
+    }
+
+    #[test]
+    fn test_serialization() {
+        let placements: Vec<Placement> = from_str("[0, lxd:1, new, foo/0, lxd]").unwrap();
+
+        assert_eq!(
+            placements,
+            vec![
+                Placement::Machine(0),
+                Placement::Container {
+                    kind: ContainerType::Lxd,
+                    machine: Some(1),
+                },
+                Placement::New,
+                Placement::Unit {
+                    container: None,
+                    application: "foo".into(),
+                    unit: Some(0),
+                },
+                Placement::Container {
+                    kind: ContainerType::Lxd,
+                    machine: None,
+                },
+            ]
+        );
+
+        let serialized = to_string(&placements).unwrap();
+        assert_eq!(
+            serialized,
+            "---\n- \"0\"\n- \"lxd:1\"\n- new\n- foo/0\n- lxd\n"
+        );
+        assert_eq!(from_str::<Vec<Placement>>(&serialized).unwrap(), placements);
+
+        let placement: Placement = "kvm:new".parse().unwrap();
+        assert_eq!(placement.to_string(), "kvm");
+    }
+}
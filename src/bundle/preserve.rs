@@ -0,0 +1,400 @@
+//! Saving bundles while keeping the layout and comments of an existing file
+//!
+//! The original file is split into its top-level sections, and any section that
+//! changed is split into its entries in turn, down to single fields such as an
+//! application's options. Entries that are unchanged are copied over verbatim,
+//! and only the rest are re-serialized. Comments that precede a section or entry
+//! are kept even if it changes, as is the spelling of aliased keys such as
+//! `num_units`.
+
+use serde::Deserialize;
+use serde_yaml::{from_value, to_string, to_value, Deserializer, Mapping, Value};
+
+use super::{overlay, Bundle};
+use crate::error::JujuError;
+
+/// A key and its value within a mapping, as written in the original file
+struct Block {
+    /// The key, with any quotes removed
+    key: String,
+
+    /// Blank lines and comments preceding the key, other than those indented past it
+    leading: String,
+
+    /// The key's line, and every line after it up to the next block
+    body: String,
+}
+
+/// Splits YAML text into blocks for each key at the given indentation
+///
+/// Returns the text before the first key, such as a `---` marker, and the blocks.
+/// Sequence items at the same indentation as a key belong to that key's block.
+/// Returns `None` if the text has a layout that can't be split safely, such as
+/// a sequence or flow mapping in place of the keys.
+fn split_blocks(text: &str, indent: usize) -> Option<(String, Vec<Block>)> {
+    let mut preamble = String::new();
+    let mut blocks: Vec<Block> = vec![];
+    let mut pending = String::new();
+
+    for line in text.split_inclusive('\n') {
+        let trimmed = line.trim_start_matches(' ');
+        let depth = line.len() - trimmed.len();
+
+        if trimmed.trim().is_empty() || trimmed.starts_with('#') {
+            pending.push_str(line);
+            continue;
+        }
+
+        if depth < indent {
+            return None;
+        }
+
+        // Sequences are often written at the same indentation as their key, e.g.
+        // `relations:\n- [a, b]`, as Juju itself does when exporting bundles
+        let is_item = depth == indent && (trimmed.starts_with("- ") || trimmed.trim() == "-");
+
+        if depth > indent || is_item {
+            match blocks.last_mut() {
+                Some(block) => {
+                    block.body.push_str(&pending);
+                    block.body.push_str(line);
+                    pending.clear();
+                    continue;
+                }
+                None => return None,
+            }
+        }
+
+        if indent == 0
+            && blocks.is_empty()
+            && (trimmed.starts_with("---") || trimmed.starts_with('%'))
+        {
+            preamble.push_str(&pending);
+            preamble.push_str(line);
+            pending.clear();
+            continue;
+        }
+
+        // Comments indented past the key, such as the last lines of a block scalar,
+        // still belong to the previous value
+        if let Some(block) = blocks.last_mut() {
+            let mut end = 0;
+            let mut offset = 0;
+            for line in pending.split_inclusive('\n') {
+                offset += line.len();
+                if !line.trim().is_empty()
+                    && line.len() - line.trim_start_matches(' ').len() > indent
+                {
+                    end = offset;
+                }
+            }
+            block.body.extend(pending.drain(..end));
+        }
+
+        blocks.push(Block {
+            key: parse_key(trimmed)?,
+            leading: std::mem::take(&mut pending),
+            body: line.to_string(),
+        });
+    }
+
+    match blocks.last_mut() {
+        Some(block) => block.body.push_str(&pending),
+        None => preamble.push_str(&pending),
+    }
+
+    for block in &mut blocks {
+        if !block.body.ends_with('\n') {
+            block.body.push('\n');
+        }
+    }
+
+    Some((preamble, blocks))
+}
+
+/// Parses the key out of a line such as `foo:`, `"0": {}` or `'bar': baz`
+fn parse_key(line: &str) -> Option<String> {
+    let (key, rest) = match line.chars().next()? {
+        quote @ '"' | quote @ '\'' => {
+            let end = line[1..].find(quote)? + 1;
+            (&line[1..end], &line[end + 1..])
+        }
+        '-' | '{' | '[' | '?' => return None,
+        _ => {
+            let end = line.find(": ").or_else(|| {
+                let trimmed = line.trim_end();
+                if trimmed.ends_with(':') {
+                    Some(trimmed.len() - 1)
+                } else {
+                    None
+                }
+            })?;
+            (&line[..end], &line[end..])
+        }
+    };
+
+    if rest.starts_with(':') {
+        Some(key.to_string())
+    } else {
+        None
+    }
+}
+
+/// Whether a block's value starts on the line after its key, e.g. `applications:`
+fn is_nested(block: &Block) -> bool {
+    let first = block.body.lines().next().unwrap_or("");
+    let value = first.split_once(':').map(|(_, v)| v).unwrap_or("");
+
+    value.trim().is_empty() || value.trim().starts_with('#')
+}
+
+/// Maps a key as written in the file to the key that `Bundle` serializes it as
+///
+/// `path` is the keys of the mappings that the key is nested in.
+fn canonical_key<'a>(path: &[&str], key: &'a str) -> &'a str {
+    match (path, key) {
+        ([], "services") => "applications",
+        (["applications", _], "num_units") => "scale",
+        _ => key,
+    }
+}
+
+/// How the original file spells keys that have aliases
+struct Style {
+    /// Whether units are given with `num_units` instead of `scale`
+    num_units: bool,
+}
+
+impl Style {
+    fn detect(original: &str) -> Self {
+        Self {
+            num_units: original
+                .lines()
+                .any(|l| l.trim_start().starts_with("num_units:")),
+        }
+    }
+
+    /// The key to write a newly added entry with
+    fn key<'a>(&self, path: &[&str], key: &'a str) -> &'a str {
+        match (path, key) {
+            (["applications", _], "scale") if self.num_units => "num_units",
+            _ => key,
+        }
+    }
+
+    /// Renames keys within a newly added value to match the file
+    fn value(&self, path: &[&str], key: &str, value: &Value) -> Value {
+        let rename = |app: &Value| match app {
+            Value::Mapping(app) => Value::Mapping(
+                app.iter()
+                    .map(|(k, v)| match k.as_str() {
+                        Some("scale") => (Value::String("num_units".into()), v.clone()),
+                        _ => (k.clone(), v.clone()),
+                    })
+                    .collect(),
+            ),
+            app => app.clone(),
+        };
+
+        match (path, key, value) {
+            _ if !self.num_units => value.clone(),
+            ([], "applications", Value::Mapping(apps)) => Value::Mapping(
+                apps.iter()
+                    .map(|(name, app)| (name.clone(), rename(app)))
+                    .collect(),
+            ),
+            (["applications"], _, app) => rename(app),
+            _ => value.clone(),
+        }
+    }
+}
+
+/// Serializes a single key and value, indented to the given depth
+fn serialize_entry(key: &str, value: &Value, indent: usize) -> Result<String, JujuError> {
+    let mut mapping = Mapping::new();
+    mapping.insert(Value::String(key.into()), value.clone());
+
+    let serialized = to_string(&mapping)?;
+    let serialized = serialized.strip_prefix("---\n").unwrap_or(&serialized);
+
+    Ok(serialized
+        .lines()
+        .map(|line| format!("{}{}\n", " ".repeat(indent), line))
+        .collect())
+}
+
+/// Renders the entries of a mapping from the original text of its body, reusing
+/// unchanged entries and recursing into changed ones
+///
+/// Returns `None` if the text can't be split into entries.
+fn render_mapping(
+    text: &str,
+    indent: usize,
+    path: &[&str],
+    old: &Mapping,
+    new: &Mapping,
+    style: &Style,
+) -> Result<Option<String>, JujuError> {
+    let (mut out, blocks) = match split_blocks(text, indent) {
+        Some(split) if !split.1.is_empty() => split,
+        _ => return Ok(None),
+    };
+
+    let mut seen = vec![];
+
+    for block in &blocks {
+        let name = canonical_key(path, &block.key);
+        let key = Value::String(name.into());
+
+        let value = match new.get(&key) {
+            Some(v) => v,
+            None => continue,
+        };
+        seen.push(key.clone());
+
+        let previous = old.get(&key);
+        out.push_str(&block.leading);
+
+        if previous == Some(value) {
+            out.push_str(&block.body);
+            continue;
+        }
+
+        if let (Some(Value::Mapping(old)), Value::Mapping(new)) = (previous, value) {
+            let path = [path, &[name]].concat();
+
+            if let Some(rendered) = render_nested(block, indent, &path, old, new, style)? {
+                out.push_str(&rendered);
+                continue;
+            }
+        }
+
+        out.push_str(&serialize_entry(
+            &block.key,
+            &style.value(path, name, value),
+            indent,
+        )?);
+    }
+
+    // Unset fields such as `name: ~` would only add noise to the file
+    for (key, value) in new {
+        if seen.contains(key) || value.is_null() {
+            continue;
+        }
+
+        let key = key.as_str().unwrap_or_default();
+        out.push_str(&serialize_entry(
+            style.key(path, key),
+            &style.value(path, key, value),
+            indent,
+        )?);
+    }
+
+    Ok(Some(out))
+}
+
+/// Renders a block whose value is a mapping written on the lines after its key
+fn render_nested(
+    block: &Block,
+    indent: usize,
+    path: &[&str],
+    old: &Mapping,
+    new: &Mapping,
+    style: &Style,
+) -> Result<Option<String>, JujuError> {
+    if !is_nested(block) {
+        return Ok(None);
+    }
+
+    let header_len = block
+        .body
+        .find('\n')
+        .map(|i| i + 1)
+        .unwrap_or(block.body.len());
+    let (header, body) = block.body.split_at(header_len);
+
+    let child_indent = match body
+        .lines()
+        .find(|l| !l.trim().is_empty() && !l.trim_start().starts_with('#'))
+    {
+        Some(line) => line.len() - line.trim_start_matches(' ').len(),
+        None => return Ok(None),
+    };
+
+    if child_indent <= indent {
+        return Ok(None);
+    }
+
+    Ok(render_mapping(body, child_indent, path, old, new, style)?
+        .map(|rendered| format!("{}{}", header, rendered)))
+}
+
+/// Renders `bundle`, keeping the layout and comments of `original` where possible
+pub(crate) fn render(bundle: &Bundle, original: &str) -> Result<String, JujuError> {
+    let fallback = || -> Result<String, JujuError> { Ok(to_string(bundle)?) };
+
+    // Overlays within the same file can't be mapped back to a single section
+    if Deserializer::from_str(original)
+        .map(Value::deserialize)
+        .count()
+        > 1
+    {
+        return fallback();
+    }
+
+    let previous: Bundle = from_value(overlay::load_documents(original.as_bytes())?)?;
+    let old = to_value(&previous)?;
+    let new = to_value(bundle)?;
+
+    let (old, new) = match (old.as_mapping(), new.as_mapping()) {
+        (Some(o), Some(n)) => (o, n),
+        _ => return fallback(),
+    };
+
+    match render_mapping(original, 0, &[], old, new, &Style::detect(original))? {
+        Some(rendered) => Ok(rendered),
+        None => fallback(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_key() {
+        assert_eq!(parse_key("foo:\n"), Some("foo".into()));
+        assert_eq!(parse_key("foo: bar\n"), Some("foo".into()));
+        assert_eq!(parse_key("\"0\": {}\n"), Some("0".into()));
+        assert_eq!(parse_key("'a b': c\n"), Some("a b".into()));
+        assert_eq!(parse_key("cs:foo\n"), None);
+        assert_eq!(parse_key("- [foo, bar]\n"), None);
+    }
+
+    #[test]
+    fn test_split_blocks() {
+        let text = "---\n# Header\nfoo: 1\n\nbar:\n  # Nested\n  baz: 2\n# Trailing\n";
+        let (preamble, blocks) = split_blocks(text, 0).unwrap();
+
+        assert_eq!(preamble, "---\n");
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].key, "foo");
+        assert_eq!(blocks[0].leading, "# Header\n");
+        assert_eq!(blocks[0].body, "foo: 1\n");
+        assert_eq!(blocks[1].leading, "\n");
+        assert_eq!(blocks[1].body, "bar:\n  # Nested\n  baz: 2\n# Trailing\n");
+
+        assert!(split_blocks("- foo\n", 0).is_none());
+
+        let (_, blocks) =
+            split_blocks("relations:\n- [a, b]\n# c\n- [a, d]\nseries: focal\n", 0).unwrap();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].body, "relations:\n- [a, b]\n# c\n- [a, d]\n");
+        assert_eq!(blocks[1].key, "series");
+
+        let (_, blocks) =
+            split_blocks("script: |\n  run\n  # done\n\n# Series\nseries: focal\n", 0).unwrap();
+        assert_eq!(blocks[0].body, "script: |\n  run\n  # done\n");
+        assert_eq!(blocks[1].leading, "\n# Series\n");
+    }
+}
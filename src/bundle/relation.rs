@@ -0,0 +1,341 @@
+//! Relations between applications in a bundle
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_derive::{Deserialize, Serialize};
+
+use super::Bundle;
+use crate::charm_source::{CharmSource, Metadata, RelationRole};
+use crate::error::JujuError;
+
+/// Every charm implicitly provides this endpoint, without declaring it in `metadata.yaml`
+const JUJU_INFO: &str = "juju-info";
+
+/// Looks up an endpoint's role and interface, including the implicit `juju-info` endpoint
+pub(crate) fn lookup_endpoint<'a>(
+    metadata: &'a Metadata,
+    name: &str,
+) -> Option<(RelationRole, &'a str)> {
+    match metadata.endpoint(name) {
+        Some((role, rel)) => Some((role, rel.interface.as_str())),
+        None if name == JUJU_INFO => Some((RelationRole::Provider, JUJU_INFO)),
+        None => None,
+    }
+}
+
+/// Lists the endpoints that a relation could use, along with their role and interface
+///
+/// If `implicit` is set, the implicit `juju-info` endpoint is included.
+fn candidate_endpoints<'a>(
+    metadata: &'a Metadata,
+    endpoint: Option<&'a str>,
+    implicit: bool,
+) -> Vec<(&'a str, RelationRole, &'a str)> {
+    match endpoint {
+        Some(name) => lookup_endpoint(metadata, name)
+            .map(|(role, interface)| vec![(name, role, interface)])
+            .unwrap_or_default(),
+        None => {
+            let mut candidates: Vec<_> = metadata
+                .endpoints()
+                .filter(|(_, role, _)| *role != RelationRole::Peer)
+                .map(|(name, role, rel)| (name, role, rel.interface.as_str()))
+                .collect();
+
+            if implicit && metadata.endpoint(JUJU_INFO).is_none() {
+                candidates.push((JUJU_INFO, RelationRole::Provider, JUJU_INFO));
+            }
+
+            candidates
+        }
+    }
+}
+
+/// Finds the pairs of endpoints that a relation between two charms could use
+///
+/// An endpoint name of `None` means that any endpoint of that charm may be used. As with
+/// Juju, the implicit `juju-info` endpoint is only considered if nothing else matches.
+pub(crate) fn matching_endpoints(
+    left: &Metadata,
+    left_endpoint: Option<&str>,
+    right: &Metadata,
+    right_endpoint: Option<&str>,
+) -> Vec<(String, String)> {
+    let find = |implicit| {
+        let rights = candidate_endpoints(right, right_endpoint, implicit);
+
+        candidate_endpoints(left, left_endpoint, implicit)
+            .into_iter()
+            .flat_map(|(ln, lr, li)| {
+                rights
+                    .iter()
+                    .filter(move |(_, rr, ri)| lr.can_relate_to(*rr) && li == *ri)
+                    .map(move |(rn, _, _)| (ln.to_string(), rn.to_string()))
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let mut matches = find(false);
+
+    if matches.is_empty() {
+        matches = find(true);
+    }
+
+    matches.sort();
+    matches
+}
+
+/// One side of a relation, such as `foo` or `foo:db`
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RelationEndpoint {
+    /// Name of the application or SAAS entry
+    pub application: String,
+
+    /// Name of the endpoint, if given
+    ///
+    /// If not set, Juju infers it from the interfaces that both charms support.
+    /// See `Bundle::resolve_relations`.
+    pub endpoint: Option<String>,
+}
+
+impl RelationEndpoint {
+    pub fn new(application: &str, endpoint: Option<&str>) -> Self {
+        Self {
+            application: application.into(),
+            endpoint: endpoint.map(String::from),
+        }
+    }
+
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let mut split = input.splitn(2, ':');
+        let application = split.next().unwrap();
+        let endpoint = split.next();
+
+        if application.is_empty() || endpoint == Some("") {
+            return Err(format!("Couldn't parse relation endpoint `{}`", input));
+        }
+
+        Ok(Self::new(application, endpoint))
+    }
+}
+
+impl FromStr for RelationEndpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl TryFrom<&str> for RelationEndpoint {
+    type Error = String;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for RelationEndpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.application)?;
+
+        if let Some(endpoint) = &self.endpoint {
+            write!(f, ":{}", endpoint)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Serialize for RelationEndpoint {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for RelationEndpoint {
+    fn deserialize<D>(deserializer: D) -> Result<RelationEndpoint, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        use serde::de::Error;
+        let s = String::deserialize(deserializer)?;
+
+        s.parse()
+            .map_err(|err| Error::custom(format!("Error deserializing RelationEndpoint: {}", err)))
+    }
+}
+
+/// A relation between two applications in a bundle
+///
+/// Represented in `bundle.yaml` as a list of two endpoints, such as `[foo:db, bar]`
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(
+    from = "(RelationEndpoint, RelationEndpoint)",
+    into = "(RelationEndpoint, RelationEndpoint)"
+)]
+pub struct BundleRelation {
+    pub left: RelationEndpoint,
+    pub right: RelationEndpoint,
+}
+
+impl BundleRelation {
+    pub fn new(left: RelationEndpoint, right: RelationEndpoint) -> Self {
+        Self { left, right }
+    }
+
+    /// The names of the applications on either side of the relation
+    pub fn applications(&self) -> [&str; 2] {
+        [&self.left.application, &self.right.application]
+    }
+
+    /// Whether either side of the relation is the given application
+    pub fn involves(&self, application: &str) -> bool {
+        self.applications().contains(&application)
+    }
+
+    /// Whether both sides of the relation have an endpoint name
+    pub fn is_resolved(&self) -> bool {
+        self.left.endpoint.is_some() && self.right.endpoint.is_some()
+    }
+
+    /// Whether two relations connect the same endpoints, in either order
+    pub fn is_equivalent(&self, other: &BundleRelation) -> bool {
+        (self.left == other.left && self.right == other.right)
+            || (self.left == other.right && self.right == other.left)
+    }
+}
+
+impl From<(RelationEndpoint, RelationEndpoint)> for BundleRelation {
+    fn from((left, right): (RelationEndpoint, RelationEndpoint)) -> Self {
+        Self::new(left, right)
+    }
+}
+
+impl From<BundleRelation> for (RelationEndpoint, RelationEndpoint) {
+    fn from(relation: BundleRelation) -> Self {
+        (relation.left, relation.right)
+    }
+}
+
+impl fmt::Display for BundleRelation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.left, self.right)
+    }
+}
+
+/// Describes possible endpoint pairs for a relation, e.g. `foo:db bar:db, foo:db2 bar:db`
+pub(crate) fn describe_matches(relation: &BundleRelation, matches: &[(String, String)]) -> String {
+    matches
+        .iter()
+        .map(|(l, r)| {
+            format!(
+                "{}:{} {}:{}",
+                relation.left.application, l, relation.right.application, r
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl Bundle {
+    /// Fills in missing endpoint names in `relations`
+    ///
+    /// A relation such as `[foo, bar]` is resolved to e.g. `[foo:db, bar:database]` by
+    /// matching up the interfaces and roles of each charm's endpoints, the same way that
+    /// Juju does. `charms` maps application names to their charm. Relations involving
+    /// SAAS entries or applications missing from `charms` are left as-is, as there's
+    /// nothing to match against.
+    ///
+    /// Fails if a relation matches either no endpoints or more than one pair of them.
+    pub fn resolve_relations(
+        &mut self,
+        charms: &HashMap<String, CharmSource>,
+    ) -> Result<(), JujuError> {
+        for relation in &mut self.relations {
+            if relation.is_resolved() {
+                continue;
+            }
+
+            let (left, right) = (&relation.left, &relation.right);
+
+            let (left_charm, right_charm) = match (
+                charms.get(&left.application),
+                charms.get(&right.application),
+            ) {
+                (Some(l), Some(r)) => (&l.metadata, &r.metadata),
+                _ => continue,
+            };
+
+            let matches = matching_endpoints(
+                left_charm,
+                left.endpoint.as_deref(),
+                right_charm,
+                right.endpoint.as_deref(),
+            );
+
+            match &matches[..] {
+                [(l, r)] => {
+                    relation.left.endpoint = Some(l.clone());
+                    relation.right.endpoint = Some(r.clone());
+                }
+                [] => return Err(JujuError::NoMatchingRelation(relation.to_string())),
+                _ => {
+                    return Err(JujuError::AmbiguousRelation(
+                        relation.to_string(),
+                        describe_matches(relation, &matches),
+                    ))
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_yaml::{from_str, to_string};
+
+    use super::*;
+
+    #[test]
+    fn test_endpoint() {
+        assert_eq!(
+            "foo:db".parse::<RelationEndpoint>().unwrap(),
+            RelationEndpoint::new("foo", Some("db"))
+        );
+        assert_eq!(
+            "foo".parse::<RelationEndpoint>().unwrap(),
+            RelationEndpoint::new("foo", None)
+        );
+
+        for endpoint in &["", ":db", "foo:"] {
+            println!("Testing {}", endpoint);
+            assert!(endpoint.parse::<RelationEndpoint>().is_err());
+        }
+    }
+
+    #[test]
+    fn test_serialization() {
+        let relation = BundleRelation::new(
+            RelationEndpoint::new("foo", Some("db")),
+            RelationEndpoint::new("bar", None),
+        );
+
+        let serialized = "---\n- \"foo:db\"\n- bar\n";
+
+        assert_eq!(&to_string(&relation).unwrap()[..], serialized);
+        assert_eq!(from_str::<BundleRelation>(serialized).unwrap(), relation);
+
+        assert!(from_str::<BundleRelation>("[foo]").is_err());
+        assert!(from_str::<BundleRelation>("[foo, bar, baz]").is_err());
+    }
+}
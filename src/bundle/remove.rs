@@ -0,0 +1,138 @@
+//! Removing a deployed bundle from a model
+
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use super::Bundle;
+use crate::cmd;
+use crate::error::JujuError;
+use crate::local::ModelRef;
+use crate::status::Status;
+
+/// How often to check the model's status while waiting for removal
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Options for `Bundle::remove`
+#[derive(Debug, Clone, Default)]
+pub struct RemoveOptions {
+    /// Destroy storage attached to the applications' units
+    pub destroy_storage: bool,
+
+    /// Remove applications even if they're in an error state
+    pub force: bool,
+
+    /// Wait up to this long for the applications and SAAS entries to go away
+    pub wait: Option<Duration>,
+
+    /// Pass `--no-prompt` to `juju remove-application`
+    ///
+    /// Juju 3 asks for confirmation before removing applications, while Juju 2.9
+    /// doesn't understand the flag.
+    pub no_prompt: bool,
+}
+
+impl Bundle {
+    /// Lists the bundle's applications and SAAS entries that exist in the model
+    ///
+    /// SAAS entries that applications outside of the bundle are still related to
+    /// are left out, as removing them would break those relations.
+    fn deployed_names(&self, status: &Status) -> (Vec<String>, Vec<String>) {
+        let apps = self
+            .applications
+            .keys()
+            .filter(|name| status.applications.contains_key(*name))
+            .cloned()
+            .collect();
+
+        let in_use = |saas: &str| {
+            status
+                .applications
+                .iter()
+                .filter(|(name, _)| !self.applications.contains_key(*name))
+                .flat_map(|(_, app)| app.relations.values().flatten())
+                .any(|related| related.application() == saas)
+        };
+
+        let saas = self
+            .saas
+            .keys()
+            .filter(|name| status.application_endpoints.contains_key(*name) && !in_use(name))
+            .cloned()
+            .collect();
+
+        (apps, saas)
+    }
+
+    /// Returns the `juju` commands that remove this bundle from a model
+    ///
+    /// Only applications and SAAS entries from the bundle that `status` shows as
+    /// present are removed. Anything else in the model is left alone, including
+    /// SAAS entries that other applications are still related to.
+    pub fn removal_commands(
+        &self,
+        model: &ModelRef,
+        status: &Status,
+        opts: &RemoveOptions,
+    ) -> Vec<Vec<String>> {
+        let (apps, saas) = self.deployed_names(status);
+        let mut commands = vec![];
+
+        if !apps.is_empty() {
+            let mut args = vec!["remove-application".into(), "-m".into(), model.to_string()];
+            if opts.destroy_storage {
+                args.push("--destroy-storage".into());
+            }
+            if opts.force {
+                args.push("--force".into());
+            }
+            if opts.no_prompt {
+                args.push("--no-prompt".into());
+            }
+            args.extend(apps);
+            commands.push(args);
+        }
+
+        if !saas.is_empty() {
+            let mut args = vec!["remove-saas".into(), "-m".into(), model.to_string()];
+            args.extend(saas);
+            commands.push(args);
+        }
+
+        commands
+    }
+
+    /// Removes this bundle's applications and SAAS entries from a model
+    pub fn remove(&self, model: &ModelRef, opts: &RemoveOptions) -> Result<(), JujuError> {
+        let model_name = model.to_string();
+        let status = Status::current(Some(&model_name))?;
+
+        for args in self.removal_commands(model, &status, opts) {
+            cmd::run("juju", &args)?;
+        }
+
+        let timeout = match opts.wait {
+            Some(t) => t,
+            None => return Ok(()),
+        };
+        let start = Instant::now();
+
+        loop {
+            let (apps, saas) = self.deployed_names(&Status::current(Some(&model_name))?);
+
+            if apps.is_empty() && saas.is_empty() {
+                return Ok(());
+            }
+
+            if start.elapsed() >= timeout {
+                let remaining: Vec<_> = apps.into_iter().chain(saas).collect();
+                return Err(JujuError::Timeout(format!(
+                    "removal of {} from {}",
+                    remaining.join(", "),
+                    model_name
+                )));
+            }
+
+            sleep(POLL_INTERVAL.min(timeout.saturating_sub(start.elapsed())));
+        }
+    }
+}
@@ -0,0 +1,12 @@
+use serde_derive::{Deserialize, Serialize};
+
+/// A remote offer consumed by the bundle
+///
+/// See the `SaasSpec` defined [here][spec] for the canonical upstream definition
+///
+/// [spec]: https://github.com/juju/charm/blob/master/bundledata.go
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub struct Saas {
+    /// URL of the offer, such as `admin/default.mysql` or `ctrl:admin/default.mysql`
+    pub url: String,
+}
@@ -0,0 +1,305 @@
+//! Rendering bundles as the equivalent `juju` CLI commands
+
+use std::collections::HashMap;
+
+use indexmap::IndexMap;
+
+use super::{Application, Bundle, Placement};
+use crate::charm_source::CharmSource;
+
+/// Builds `--resource` arguments for `juju deploy` and `juju upgrade-charm`
+pub(crate) fn resource_args(
+    resources: &IndexMap<String, String>,
+) -> impl Iterator<Item = String> + '_ {
+    resources
+        .iter()
+        .map(|(k, v)| format!("--resource={}={}", k, v))
+}
+
+/// Quotes an argument for a POSIX shell, if it needs it
+fn quote(arg: &str) -> String {
+    let safe = |ch: char| ch.is_ascii_alphanumeric() || "-_=:/.,@+%".contains(ch);
+
+    if !arg.is_empty() && arg.chars().all(safe) {
+        arg.into()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+/// Translates a placement directive to a `--to` argument
+///
+/// Bundle machine IDs are mapped to the IDs that `juju add-machine` will give them.
+/// Returns `None` for directives that the CLI can't express, such as placing a unit
+/// alongside another application's unit, and for new machines, as that's the default.
+fn placement_arg(placement: &Placement, machines: &IndexMap<String, usize>) -> Option<String> {
+    let machine = |id: &u32| {
+        machines
+            .get(&id.to_string())
+            .map(ToString::to_string)
+            .unwrap_or_else(|| id.to_string())
+    };
+
+    match placement {
+        Placement::Machine(id) => Some(machine(id)),
+        Placement::Container {
+            kind,
+            machine: Some(id),
+        } => Some(format!("{}:{}", kind, machine(id))),
+        Placement::Container {
+            kind,
+            machine: None,
+        } => Some(kind.to_string()),
+        Placement::Zone(zone) => Some(format!("zone={}", zone)),
+        Placement::New | Placement::Unit { .. } => None,
+    }
+}
+
+impl Application {
+    /// Builds the arguments for `juju deploy`, apart from unit count and placement
+    ///
+    /// Includes `--channel`, `--revision`, `--series`, `--constraints`, `--config` and `--resource`
+    /// flags, as well as storage and devices. Local charms are deployed from `source`.
+    /// Options set to null are left out, so that the charm's default is used.
+    pub fn deploy_args(&self, name: &str) -> Vec<String> {
+        let charm = match (&self.charm, &self.source) {
+            (Some(charm), _) => charm.to_string(),
+            (None, Some(source)) => source.clone(),
+            (None, None) => name.into(),
+        };
+
+        let mut args = vec!["deploy".to_string(), charm, name.into()];
+
+        if let Some(channel) = &self.channel {
+            args.push(format!("--channel={}", channel));
+        }
+
+        if let Some(revision) = self.revision {
+            args.push(format!("--revision={}", revision));
+        }
+
+        if let Some(series) = &self.series {
+            args.push(format!("--series={}", series));
+        }
+
+        if let Some(constraints) = &self.constraints {
+            args.push(format!("--constraints={}", constraints));
+        }
+
+        for (key, value) in self.options.iter().chain(self.config.iter()) {
+            if !value.is_none() {
+                args.push(format!("--config={}={}", key, value));
+            }
+        }
+
+        args.extend(resource_args(&self.resources));
+
+        for (key, directive) in &self.storage {
+            args.push(format!("--storage={}={}", key, directive));
+        }
+
+        for (key, directive) in &self.devices {
+            args.push(format!("--device={}={}", key, directive));
+        }
+
+        args
+    }
+}
+
+/// A command to run, along with notes about anything it doesn't do
+type Annotated = (Vec<String>, Vec<String>);
+
+/// Notes a unit whose placement `placement_arg` can't express
+fn unplaced_note(name: &str, index: u32, placement: Option<&Placement>) -> Option<String> {
+    match placement? {
+        placement @ Placement::Unit { .. } => Some(format!(
+            "Can't place {}/{} with `--to={}`, so it goes on a new machine instead",
+            name, index, placement
+        )),
+        _ => None,
+    }
+}
+
+impl Bundle {
+    /// Lists the `juju` commands that would deploy this bundle to an empty model
+    ///
+    /// Machines are added first, followed by SAAS entries, applications, extra units,
+    /// relations, and finally `juju expose` and `juju trust`. Relations use
+    /// `juju integrate` if `integrate` is set, as in Juju 3, and `juju relate`
+    /// otherwise. Each command is returned as its arguments, without `juju` itself.
+    ///
+    /// Applications without any units are deployed with `--num-units=0`, unless
+    /// `charms`, which maps application names to their charm, says that they're
+    /// subordinates. Juju refuses `--num-units` for subordinates, so include the
+    /// charms of any subordinates in the bundle.
+    ///
+    /// Units placed alongside another application's units, such as `foo/0`, can't be
+    /// placed from the CLI, and go on new machines. `Bundle::to_script` notes these.
+    pub fn commands(
+        &self,
+        integrate: bool,
+        charms: &HashMap<String, CharmSource>,
+    ) -> Vec<Vec<String>> {
+        self.annotated_commands(integrate, charms)
+            .into_iter()
+            .map(|(command, _)| command)
+            .collect()
+    }
+
+    /// Lists `Bundle::commands`, along with notes about placements each one leaves out
+    fn annotated_commands(
+        &self,
+        integrate: bool,
+        charms: &HashMap<String, CharmSource>,
+    ) -> Vec<Annotated> {
+        let mut commands: Vec<Annotated> = vec![];
+        let series = self.series.as_ref().map(ToString::to_string);
+
+        let mut machines = IndexMap::new();
+
+        for (index, (id, machine)) in self.machines.iter().enumerate() {
+            let mut args = vec!["add-machine".to_string()];
+
+            if let Some(series) = machine.series.as_ref().or(series.as_ref()) {
+                args.push(format!("--series={}", series));
+            }
+
+            if let Some(constraints) = &machine.constraints {
+                args.push(format!("--constraints={}", constraints));
+            }
+
+            machines.insert(id.clone(), index);
+            commands.push((args, vec![]));
+        }
+
+        for (name, saas) in &self.saas {
+            commands.push((
+                vec!["consume".into(), saas.url.clone(), name.clone()],
+                vec![],
+            ));
+        }
+
+        let mut extra_units = vec![];
+
+        for (name, app) in &self.applications {
+            let mut args = app.deploy_args(name);
+            let mut notes = vec![];
+
+            if app.series.is_none() {
+                if let Some(series) = &series {
+                    args.push(format!("--series={}", series));
+                }
+            }
+
+            let subordinate = charms
+                .get(name)
+                .map(|c| c.metadata.subordinate)
+                .unwrap_or(false);
+
+            if app.scale == 0 && !subordinate {
+                args.push("--num-units=0".into());
+            }
+
+            if app.scale > 0 {
+                match app.placement(0).and_then(|p| placement_arg(p, &machines)) {
+                    Some(to) => args.push(format!("--to={}", to)),
+                    None => notes.extend(unplaced_note(name, 0, app.placement(0))),
+                }
+            }
+
+            commands.push((args, notes));
+
+            // Units with their own placement get their own command, and the rest are grouped
+            let mut unplaced = 0;
+            let mut notes = vec![];
+
+            for index in 1..app.scale {
+                match app
+                    .placement(index)
+                    .and_then(|p| placement_arg(p, &machines))
+                {
+                    Some(to) => extra_units.push((
+                        vec!["add-unit".to_string(), name.clone(), format!("--to={}", to)],
+                        vec![],
+                    )),
+                    None => {
+                        unplaced += 1;
+                        notes.extend(unplaced_note(name, index, app.placement(index)));
+                    }
+                }
+            }
+
+            if unplaced > 0 {
+                extra_units.push((
+                    vec![
+                        "add-unit".to_string(),
+                        name.clone(),
+                        format!("--num-units={}", unplaced),
+                    ],
+                    notes,
+                ));
+            }
+        }
+
+        commands.extend(extra_units);
+
+        let relate = if integrate { "integrate" } else { "relate" };
+        for relation in &self.relations {
+            commands.push((
+                vec![
+                    relate.into(),
+                    relation.left.to_string(),
+                    relation.right.to_string(),
+                ],
+                vec![],
+            ));
+        }
+
+        for (name, app) in &self.applications {
+            if app.expose {
+                commands.push((vec!["expose".into(), name.clone()], vec![]));
+            }
+
+            if app.trust {
+                commands.push((vec!["trust".into(), name.clone()], vec![]));
+            }
+        }
+
+        commands
+    }
+
+    /// Renders `Bundle::commands` as a shell script
+    ///
+    /// Placements that the commands can't express are noted in comments.
+    pub fn to_script(&self, integrate: bool, charms: &HashMap<String, CharmSource>) -> String {
+        let mut script = String::from("#!/bin/sh\nset -e\n\n");
+
+        for (command, notes) in self.annotated_commands(integrate, charms) {
+            for note in notes {
+                script.push_str(&format!("# {}\n", note));
+            }
+
+            let args: Vec<_> = command.iter().map(|a| quote(a)).collect();
+            script.push_str(&format!("juju {}\n", args.join(" ")));
+        }
+
+        script
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote("cs:foo-1"), "cs:foo-1");
+        assert_eq!(quote("--to=lxd:0"), "--to=lxd:0");
+        assert_eq!(
+            quote("--constraints=cores=2 mem=4G"),
+            "'--constraints=cores=2 mem=4G'"
+        );
+        assert_eq!(quote("it's"), "'it'\\''s'");
+        assert_eq!(quote(""), "''");
+    }
+}
@@ -0,0 +1,237 @@
+//! Validation of bundles against charm metadata
+
+use std::collections::HashMap;
+use std::fmt;
+
+use indexmap::IndexMap;
+
+use super::relation::{describe_matches, lookup_endpoint, matching_endpoints, BundleRelation};
+use super::{Application, Bundle};
+use crate::charm_source::CharmSource;
+
+/// How serious a diagnostic is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// The bundle won't deploy
+    Error,
+
+    /// The bundle couldn't be fully checked, or might not do what was intended
+    Warning,
+}
+
+/// A problem found while validating a bundle
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+
+    /// The application that the problem is with, if it's specific to one
+    pub application: Option<String>,
+
+    pub message: String,
+}
+
+impl Diagnostic {
+    fn error(application: Option<&str>, message: String) -> Self {
+        Self {
+            severity: Severity::Error,
+            application: application.map(String::from),
+            message,
+        }
+    }
+
+    fn warning(application: Option<&str>, message: String) -> Self {
+        Self {
+            severity: Severity::Warning,
+            application: application.map(String::from),
+            message,
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.severity {
+            Severity::Error => f.write_str("error: ")?,
+            Severity::Warning => f.write_str("warning: ")?,
+        }
+
+        if let Some(app) = &self.application {
+            write!(f, "{}: ", app)?;
+        }
+
+        f.write_str(&self.message)
+    }
+}
+
+impl Bundle {
+    /// Validates the bundle against the metadata of the charms it deploys
+    ///
+    /// `charms` maps application names to their charm. Checks that:
+    ///
+    ///  - Relations point at real endpoints with matching interfaces
+    ///  - Config options exist and have the right type
+    ///  - Resources, storage and devices are declared by the charm
+    ///  - Subordinate charms have a scale of 0
+    ///  - Placement directives refer to machines defined in the bundle
+    ///
+    /// Applications without an entry in `charms` are skipped with a warning.
+    pub fn validate(&self, charms: &HashMap<String, CharmSource>) -> Vec<Diagnostic> {
+        let mut diagnostics = vec![];
+
+        let mut names: Vec<_> = self.applications.keys().collect();
+        names.sort();
+
+        for name in names {
+            let app = &self.applications[name];
+
+            for placement in &app.to {
+                if let Some(id) = placement.machine() {
+                    if !self.machines.contains_key(&id.to_string()) {
+                        diagnostics.push(Diagnostic::error(
+                            Some(name),
+                            format!("Placement `{}` refers to an undefined machine", placement),
+                        ));
+                    }
+                }
+            }
+
+            match charms.get(name) {
+                Some(charm) => validate_application(name, app, charm, &mut diagnostics),
+                None => diagnostics.push(Diagnostic::warning(
+                    Some(name),
+                    "No charm metadata available, skipping checks".into(),
+                )),
+            }
+        }
+
+        for relation in &self.relations {
+            self.validate_relation(relation, charms, &mut diagnostics);
+        }
+
+        diagnostics
+    }
+
+    fn validate_relation(
+        &self,
+        relation: &BundleRelation,
+        charms: &HashMap<String, CharmSource>,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) {
+        for app in &relation.applications() {
+            if !self.applications.contains_key(*app) && !self.saas.contains_key(*app) {
+                diagnostics.push(Diagnostic::error(
+                    None,
+                    format!(
+                        "Relation `{}` refers to unknown application `{}`",
+                        relation, app
+                    ),
+                ));
+                return;
+            }
+        }
+
+        let (left, right) = (&relation.left, &relation.right);
+
+        // Nothing to check against for SAAS entries, or apps with no metadata
+        let (left_charm, right_charm) = match (
+            charms.get(&left.application),
+            charms.get(&right.application),
+        ) {
+            (Some(l), Some(r)) => (&l.metadata, &r.metadata),
+            _ => return,
+        };
+
+        for (side, metadata) in &[(left, left_charm), (right, right_charm)] {
+            if let Some(endpoint) = &side.endpoint {
+                if lookup_endpoint(metadata, endpoint).is_none() {
+                    diagnostics.push(Diagnostic::error(
+                        Some(&side.application),
+                        format!("Charm has no relation endpoint `{}`", endpoint),
+                    ));
+                    return;
+                }
+            }
+        }
+
+        let matches = matching_endpoints(
+            left_charm,
+            left.endpoint.as_deref(),
+            right_charm,
+            right.endpoint.as_deref(),
+        );
+
+        match matches.len() {
+            0 => diagnostics.push(Diagnostic::error(
+                None,
+                format!(
+                    "Relation `{}` has no endpoints with a matching interface",
+                    relation
+                ),
+            )),
+            1 => {}
+            _ => diagnostics.push(Diagnostic::error(
+                None,
+                format!(
+                    "Relation `{}` is ambiguous, it could use any of: {}",
+                    relation,
+                    describe_matches(relation, &matches)
+                ),
+            )),
+        }
+    }
+}
+
+fn validate_application(
+    name: &str,
+    app: &Application,
+    charm: &CharmSource,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let metadata = &charm.metadata;
+
+    // Keys set in both `options` and `config` are only checked once
+    let mut options: IndexMap<_, _> = app.options.iter().chain(app.config.iter()).collect();
+    options.sort_keys();
+
+    for (key, value) in options {
+        let option = charm.config.as_ref().and_then(|c| c.options.get(key));
+
+        match option.map(|o| value.check(o)) {
+            None => diagnostics.push(Diagnostic::error(
+                Some(name),
+                format!("Charm has no config option `{}`", key),
+            )),
+            Some(Err(err)) => diagnostics.push(Diagnostic::error(
+                Some(name),
+                format!("Config option `{}` {}", key, err),
+            )),
+            Some(Ok(())) => {}
+        }
+    }
+
+    let mut resources: Vec<_> = app.resources.keys().collect();
+    resources.sort();
+
+    for resource in resources {
+        if !metadata.resources.contains_key(resource) {
+            diagnostics.push(Diagnostic::error(
+                Some(name),
+                format!("Charm has no resource `{}`", resource),
+            ));
+        }
+    }
+
+    for error in app.directive_errors(metadata) {
+        diagnostics.push(Diagnostic::error(Some(name), error));
+    }
+
+    if metadata.subordinate && app.scale != 0 {
+        diagnostics.push(Diagnostic::error(
+            Some(name),
+            format!(
+                "Subordinate charms must have a scale of 0, not {}",
+                app.scale
+            ),
+        ));
+    }
+}
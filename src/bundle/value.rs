@@ -0,0 +1,236 @@
+use std::fmt;
+
+use indexmap::IndexMap;
+use serde_derive::{Deserialize, Serialize};
+use serde_yaml::Value as YamlValue;
+
+use crate::charm_source::ConfigOption;
+
+/// Represents a YAML value that doesn't have a pre-determined type
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    List(Vec<Value>),
+    Map(IndexMap<String, Value>),
+    None,
+}
+
+impl Value {
+    pub fn is_none(&self) -> bool {
+        matches!(self, Value::None)
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Value::Integer(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    /// Gets the value as a float, including integer values
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Float(f) => Some(*f),
+            Value::Integer(i) => Some(*i as f64),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Boolean(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_list(&self) -> Option<&[Value]> {
+        match self {
+            Value::List(l) => Some(l),
+            _ => None,
+        }
+    }
+
+    pub fn as_map(&self) -> Option<&IndexMap<String, Value>> {
+        match self {
+            Value::Map(m) => Some(m),
+            _ => None,
+        }
+    }
+
+    /// Checks that this value has the type that a charm config option expects
+    ///
+    /// Unset values are always valid, and integers are accepted for float options.
+    pub fn check(&self, option: &ConfigOption) -> Result<(), String> {
+        let valid = match option {
+            _ if self.is_none() => true,
+            ConfigOption::String { .. } => self.as_str().is_some(),
+            ConfigOption::Integer { .. } => self.as_i64().is_some(),
+            ConfigOption::Float { .. } => self.as_f64().is_some(),
+            ConfigOption::Boolean { .. } => self.as_bool().is_some(),
+        };
+
+        if valid {
+            Ok(())
+        } else {
+            Err(format!("should be of type {}", option.type_name()))
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    /// Formats the value inline, with lists and maps in YAML flow style
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::String(s) => f.write_str(s),
+            Value::Integer(i) => write!(f, "{}", i),
+            Value::Float(n) => write!(f, "{}", n),
+            Value::Boolean(b) => write!(f, "{}", b),
+            Value::None => f.write_str("~"),
+            Value::List(l) => {
+                let items: Vec<_> = l.iter().map(ToString::to_string).collect();
+                write!(f, "[{}]", items.join(", "))
+            }
+            Value::Map(m) => {
+                let items: Vec<_> = m.iter().map(|(k, v)| format!("{}: {}", k, v)).collect();
+                write!(f, "{{{}}}", items.join(", "))
+            }
+        }
+    }
+}
+
+impl From<YamlValue> for Value {
+    fn from(value: YamlValue) -> Self {
+        match value {
+            YamlValue::Null => Value::None,
+            YamlValue::Bool(b) => Value::Boolean(b),
+            YamlValue::Number(n) => match n.as_i64() {
+                Some(i) => Value::Integer(i),
+                None => Value::Float(n.as_f64().unwrap_or(f64::NAN)),
+            },
+            YamlValue::String(s) => Value::String(s),
+            YamlValue::Sequence(s) => Value::List(s.into_iter().map(Value::from).collect()),
+            YamlValue::Mapping(m) => Value::Map(
+                m.into_iter()
+                    .map(|(k, v)| (yaml_key(k), Value::from(v)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl From<Value> for YamlValue {
+    fn from(value: Value) -> Self {
+        match value {
+            Value::None => YamlValue::Null,
+            Value::Boolean(b) => YamlValue::Bool(b),
+            Value::Integer(i) => YamlValue::Number(i.into()),
+            Value::Float(f) => YamlValue::Number(f.into()),
+            Value::String(s) => YamlValue::String(s),
+            Value::List(l) => YamlValue::Sequence(l.into_iter().map(YamlValue::from).collect()),
+            Value::Map(m) => YamlValue::Mapping(
+                m.into_iter()
+                    .map(|(k, v)| (YamlValue::String(k), YamlValue::from(v)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+/// Converts a YAML mapping key to a string, as bundles only use string keys
+fn yaml_key(key: YamlValue) -> String {
+    match key {
+        YamlValue::String(s) => s,
+        YamlValue::Number(n) => n.to_string(),
+        YamlValue::Bool(b) => b.to_string(),
+        other => serde_yaml::to_string(&other)
+            .map(|s| s.trim_start_matches("---").trim().to_string())
+            .unwrap_or_default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_yaml::from_str;
+
+    use super::*;
+
+    #[test]
+    fn test_deserialize() {
+        let parsed: IndexMap<String, Value> =
+            from_str("{s: foo, i: 1, f: 1.5, b: true, n: ~, l: [1, a], m: {x: 1}}").unwrap();
+
+        assert_eq!(parsed["s"], Value::String("foo".into()));
+        assert_eq!(parsed["i"], Value::Integer(1));
+        assert_eq!(parsed["f"], Value::Float(1.5));
+        assert_eq!(parsed["b"], Value::Boolean(true));
+        assert_eq!(parsed["n"], Value::None);
+        assert_eq!(
+            parsed["l"],
+            Value::List(vec![Value::Integer(1), Value::String("a".into())])
+        );
+        assert_eq!(
+            parsed["m"],
+            Value::Map([("x".to_string(), Value::Integer(1))].into())
+        );
+    }
+
+    #[test]
+    fn test_yaml_conversion() {
+        let yaml: YamlValue = from_str("{1: [a, 2.5], b: {c: true}, d: ~}").unwrap();
+        let value = Value::from(yaml);
+
+        assert_eq!(
+            value,
+            Value::Map(
+                [
+                    (
+                        "1".to_string(),
+                        Value::List(vec![Value::String("a".into()), Value::Float(2.5)])
+                    ),
+                    (
+                        "b".to_string(),
+                        Value::Map([("c".to_string(), Value::Boolean(true))].into())
+                    ),
+                    ("d".to_string(), Value::None),
+                ]
+                .into()
+            )
+        );
+
+        let round_tripped: YamlValue = value.clone().into();
+        assert_eq!(Value::from(round_tripped), value);
+    }
+
+    #[test]
+    fn test_check() {
+        let float = ConfigOption::Float {
+            default: 1.0,
+            description: String::new(),
+        };
+        let string = ConfigOption::String {
+            default: None,
+            description: String::new(),
+        };
+
+        assert!(Value::Float(1.5).check(&float).is_ok());
+        assert!(Value::Integer(1).check(&float).is_ok());
+        assert!(Value::None.check(&float).is_ok());
+        assert!(Value::String("1.5".into()).check(&float).is_err());
+        assert!(Value::Integer(1).check(&string).is_err());
+        assert_eq!(
+            Value::Boolean(true).check(&string).unwrap_err(),
+            "should be of type string"
+        );
+    }
+}
@@ -1,3 +1,4 @@
+use std::fmt;
 use std::str::FromStr;
 
 use serde_derive::{Deserialize, Serialize};
@@ -13,16 +14,9 @@ pub enum Channel {
     Stable,
 }
 
-impl ToString for Channel {
-    fn to_string(&self) -> String {
-        match self {
-            Channel::Unpublished => "unpublished",
-            Channel::Edge => "edge",
-            Channel::Beta => "beta",
-            Channel::Candidate => "candidate",
-            Channel::Stable => "stable",
-        }
-        .into()
+impl fmt::Display for Channel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str((*self).into())
     }
 }
 
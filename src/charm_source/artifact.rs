@@ -0,0 +1,269 @@
+//! Built `.charm` files, and working out which bases they're for
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use ex::fs::read_dir;
+
+use super::{Base, Platform};
+use crate::error::JujuError;
+
+/// A `.charm` file produced by building a charm
+#[derive(Debug, Clone, PartialEq)]
+pub struct Artifact {
+    pub path: PathBuf,
+
+    /// The bases the charm runs on, each with the architectures it was built for
+    pub bases: Vec<Base>,
+}
+
+impl Artifact {
+    /// Works out the bases a charm was built for from its filename
+    ///
+    /// Understands the names that charmcraft gives built charms, such as
+    /// `foo_ubuntu-20.04-amd64.charm`, `foo_ubuntu-20.04-amd64_ubuntu-22.04-amd64-arm64.charm`
+    /// and `foo_ubuntu@22.04-amd64.charm`. Names that only give an architecture, such
+    /// as `foo_arm64.charm`, are matched up with `run_on`. Returns `None` if the file
+    /// isn't a build of the charm called `name`.
+    pub fn parse<P: Into<PathBuf>>(name: &str, path: P, run_on: &[Base]) -> Option<Self> {
+        let path = path.into();
+        let stem = path
+            .file_name()?
+            .to_str()?
+            .strip_suffix(".charm")?
+            .strip_prefix(name)?
+            .strip_prefix('_')?;
+
+        let mut bases = vec![];
+
+        for segment in stem.split('_') {
+            let mut parts = segment.split('-');
+            let first = parts.next()?;
+
+            let base = match first.split_once('@') {
+                Some((name, channel)) => Base {
+                    name: name.into(),
+                    channel: channel.into(),
+                    architectures: parts.map(String::from).collect(),
+                },
+                None => match parts.next() {
+                    Some(channel) => Base {
+                        name: first.into(),
+                        channel: channel.into(),
+                        architectures: parts.map(String::from).collect(),
+                    },
+                    None => {
+                        bases.extend(run_on.iter().map(|b| Base {
+                            architectures: vec![first.into()],
+                            ..b.clone()
+                        }));
+                        continue;
+                    }
+                },
+            };
+
+            bases.push(base);
+        }
+
+        Some(Self { path, bases })
+    }
+
+    /// Whether the charm runs on the given series and architecture
+    ///
+    /// The series may be given as either a name such as `jammy`, or a channel such
+    /// as `22.04`. Either may be `None` to match any.
+    pub fn supports(&self, series: Option<&str>, arch: Option<&str>) -> bool {
+        self.bases.iter().any(|b| {
+            series.map(|s| b.matches(s)).unwrap_or(true)
+                && arch
+                    .map(|a| b.architectures.iter().any(|ba| ba == a))
+                    .unwrap_or(true)
+        })
+    }
+
+    /// Whether the charm runs on the given platform
+    pub fn covers(&self, platform: &Platform) -> bool {
+        self.bases.iter().any(|b| {
+            b.name == platform.name
+                && b.channel == platform.channel
+                && b.architectures.contains(&platform.arch)
+        })
+    }
+
+    /// Picks the first artifact that runs on the given series and architecture
+    pub fn select<'a>(
+        artifacts: &'a [Artifact],
+        series: Option<&str>,
+        arch: Option<&str>,
+    ) -> Option<&'a Artifact> {
+        artifacts.iter().find(|a| a.supports(series, arch))
+    }
+
+    /// Finds the `.charm` files named in charmcraft's output
+    ///
+    /// Relative paths are resolved against `dir`, and files that don't exist are skipped.
+    pub fn from_output(name: &str, output: &str, dir: &Path, run_on: &[Base]) -> Vec<Self> {
+        let mut artifacts: Vec<Self> = vec![];
+
+        let files = output
+            .split_whitespace()
+            .map(|word| {
+                word.trim_end_matches(&['.', ',', ':'][..])
+                    .trim_matches(&['\'', '"'][..])
+            })
+            .filter(|word| word.ends_with(".charm"));
+
+        for file in files {
+            let path = dir.join(file);
+
+            if !path.is_file() || artifacts.iter().any(|a| a.path == path) {
+                continue;
+            }
+
+            if let Some(artifact) = Self::parse(name, path, run_on) {
+                artifacts.push(artifact);
+            }
+        }
+
+        artifacts
+    }
+
+    /// Finds the builds of the charm called `name` in `dir`, along with when they were written
+    pub(crate) fn scan(
+        name: &str,
+        dir: &Path,
+        run_on: &[Base],
+    ) -> Result<HashMap<PathBuf, (Self, Option<SystemTime>)>, JujuError> {
+        let mut found = HashMap::new();
+
+        for entry in read_dir(dir)? {
+            let entry = entry?;
+            let path = entry.path();
+
+            if let Some(artifact) = Self::parse(name, &path, run_on) {
+                let modified = entry.metadata().and_then(|m| m.modified()).ok();
+                found.insert(path, (artifact, modified));
+            }
+        }
+
+        Ok(found)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn base(name: &str, channel: &str, architectures: &[&str]) -> Base {
+        Base {
+            name: name.into(),
+            channel: channel.into(),
+            architectures: architectures.iter().map(|a| a.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let run_on = [base("ubuntu", "22.04", &[])];
+
+        let artifact = Artifact::parse("foo-bar", "foo-bar_ubuntu-20.04-amd64.charm", &run_on);
+        assert_eq!(
+            artifact.unwrap().bases,
+            vec![base("ubuntu", "20.04", &["amd64"])]
+        );
+
+        let artifact = Artifact::parse(
+            "foo",
+            "/tmp/foo_ubuntu-20.04-amd64_ubuntu-22.04-amd64-arm64.charm",
+            &run_on,
+        );
+        assert_eq!(
+            artifact.unwrap().bases,
+            vec![
+                base("ubuntu", "20.04", &["amd64"]),
+                base("ubuntu", "22.04", &["amd64", "arm64"])
+            ]
+        );
+
+        let artifact = Artifact::parse("foo", "foo_ubuntu@24.04-riscv64.charm", &run_on);
+        assert_eq!(
+            artifact.unwrap().bases,
+            vec![base("ubuntu", "24.04", &["riscv64"])]
+        );
+
+        let artifact = Artifact::parse("foo", "foo_arm64.charm", &run_on);
+        assert_eq!(
+            artifact.unwrap().bases,
+            vec![base("ubuntu", "22.04", &["arm64"])]
+        );
+
+        assert_eq!(
+            Artifact::parse("foo", "foo-bar_ubuntu-20.04-amd64.charm", &run_on),
+            None
+        );
+        assert_eq!(
+            Artifact::parse("foo", "foo_ubuntu-20.04-amd64.zip", &run_on),
+            None
+        );
+    }
+
+    #[test]
+    fn test_from_output() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in &[
+            "foo_ubuntu-20.04-amd64.charm",
+            "foo_ubuntu-22.04-arm64.charm",
+        ] {
+            std::fs::write(dir.path().join(name), "zip").unwrap();
+        }
+
+        let output = "Packing the charm.\n\
+                      Created 'foo_ubuntu-20.04-amd64.charm'.\n\
+                      Charms packed:\n    \
+                      foo_ubuntu-20.04-amd64.charm\n    \
+                      foo_ubuntu-22.04-arm64.charm\n    \
+                      foo_ubuntu-24.04-amd64.charm\n";
+
+        let paths: Vec<_> = Artifact::from_output("foo", output, dir.path(), &[])
+            .into_iter()
+            .map(|a| a.path)
+            .collect();
+
+        assert_eq!(
+            paths,
+            vec![
+                dir.path().join("foo_ubuntu-20.04-amd64.charm"),
+                dir.path().join("foo_ubuntu-22.04-arm64.charm")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_select() {
+        let artifacts = [
+            Artifact::parse("foo", "foo_ubuntu-20.04-amd64.charm", &[]).unwrap(),
+            Artifact::parse("foo", "foo_ubuntu-22.04-amd64-arm64.charm", &[]).unwrap(),
+        ];
+
+        let select = |series, arch| Artifact::select(&artifacts, series, arch).map(|a| &a.path);
+
+        assert_eq!(select(None, None), Some(&artifacts[0].path));
+        assert_eq!(select(Some("jammy"), None), Some(&artifacts[1].path));
+        assert_eq!(
+            select(Some("22.04"), Some("arm64")),
+            Some(&artifacts[1].path)
+        );
+        assert_eq!(select(None, Some("arm64")), Some(&artifacts[1].path));
+        assert_eq!(select(Some("focal"), Some("arm64")), None);
+        assert_eq!(select(Some("noble"), None), None);
+
+        let platform = Platform {
+            name: "ubuntu".into(),
+            channel: "22.04".into(),
+            arch: "arm64".into(),
+        };
+        assert!(!artifacts[0].covers(&platform));
+        assert!(artifacts[1].covers(&platform));
+    }
+}
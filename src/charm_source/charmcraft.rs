@@ -1,10 +1,108 @@
+use std::fmt;
+
+use indexmap::IndexMap;
 use serde_derive::{Deserialize, Serialize};
+use serde_yaml::Value;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "kebab-case")]
 pub struct Base {
     pub name: String,
     pub channel: String,
+
+    /// Architectures this base applies to
+    ///
+    /// If empty, charmcraft defaults to the architecture of the build host.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub architectures: Vec<String>,
+}
+
+/// Ubuntu release channels and their series names
+const UBUNTU_SERIES: &[(&str, &str)] = &[
+    ("14.04", "trusty"),
+    ("16.04", "xenial"),
+    ("18.04", "bionic"),
+    ("20.04", "focal"),
+    ("20.10", "groovy"),
+    ("21.04", "hirsute"),
+    ("21.10", "impish"),
+    ("22.04", "jammy"),
+    ("22.10", "kinetic"),
+    ("23.04", "lunar"),
+    ("23.10", "mantic"),
+    ("24.04", "noble"),
+];
+
+impl Base {
+    /// The series name for this base, such as `jammy` for Ubuntu 22.04
+    pub fn series(&self) -> Option<&'static str> {
+        if self.name != "ubuntu" {
+            return None;
+        }
+
+        UBUNTU_SERIES
+            .iter()
+            .find(|(channel, _)| *channel == self.channel)
+            .map(|(_, series)| *series)
+    }
+
+    /// Whether this base is the given series or channel, e.g. `jammy` or `22.04`
+    pub fn matches(&self, series: &str) -> bool {
+        self.channel == series || self.series() == Some(series)
+    }
+
+    /// Whether the given series or channel is one that `Base::matches` can map to an
+    /// Ubuntu base
+    ///
+    /// Series that don't name an OS release, such as `kubernetes`, aren't.
+    pub fn is_known_series(series: &str) -> bool {
+        UBUNTU_SERIES
+            .iter()
+            .any(|(channel, name)| *channel == series || *name == series)
+    }
+}
+
+/// A single base and architecture that a charm runs on
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Platform {
+    pub name: String,
+    pub channel: String,
+    pub arch: String,
+}
+
+impl Platform {
+    /// Whether this is the given series and architecture
+    ///
+    /// As with `Artifact::supports`, either may be `None` to match any.
+    pub fn supports(&self, series: Option<&str>, arch: Option<&str>) -> bool {
+        let base = Base {
+            name: self.name.clone(),
+            channel: self.channel.clone(),
+            architectures: vec![],
+        };
+
+        series.map(|s| base.matches(s)).unwrap_or(true)
+            && arch.map(|a| a == self.arch).unwrap_or(true)
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}@{}/{}", self.name, self.channel, self.arch)
+    }
+}
+
+/// The architecture of the machine we're running on, as named by Debian
+fn host_arch() -> String {
+    match std::env::consts::ARCH {
+        "x86_64" => "amd64",
+        "aarch64" => "arm64",
+        "powerpc64" => "ppc64el",
+        "arm" => "armhf",
+        other => other,
+    }
+    .into()
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -21,4 +119,64 @@ pub struct Charmcraft {
     pub bases: Vec<BaseSpec>,
     #[serde(default)]
     pub architectures: Vec<String>,
+
+    /// How to build each part of the charm, keyed by part name
+    #[serde(default)]
+    #[serde(skip_serializing_if = "IndexMap::is_empty")]
+    pub parts: IndexMap<String, Value>,
+}
+
+impl Charmcraft {
+    /// The architectures the charm is built for on the given base
+    ///
+    /// Bases without any architectures fall back to the top-level `architectures`,
+    /// and then to the architecture of this machine, which is what charmcraft
+    /// builds for by default.
+    pub fn architectures_for(&self, base: &Base) -> Vec<String> {
+        if !base.architectures.is_empty() {
+            base.architectures.clone()
+        } else if !self.architectures.is_empty() {
+            self.architectures.clone()
+        } else {
+            vec![host_arch()]
+        }
+    }
+
+    /// Every base and architecture combination that the charm runs on
+    ///
+    /// See `Charmcraft::architectures_for`.
+    pub fn platforms(&self) -> Vec<Platform> {
+        let mut platforms = vec![];
+
+        for base in self.bases.iter().flat_map(|spec| &spec.run_on) {
+            for arch in self.architectures_for(base) {
+                let platform = Platform {
+                    name: base.name.clone(),
+                    channel: base.channel.clone(),
+                    arch,
+                };
+
+                if !platforms.contains(&platform) {
+                    platforms.push(platform);
+                }
+            }
+        }
+
+        platforms
+    }
+
+    /// Lists parts that are built with something other than the `charm` plugin
+    ///
+    /// Only charms without any of these can be packed by `CharmSource::pack_native`.
+    pub fn compiled_parts(&self) -> Vec<&str> {
+        self.parts
+            .iter()
+            .filter(|(name, part)| {
+                // Parts without a plugin use the one with the same name as the part
+                let plugin = part.get("plugin").and_then(Value::as_str);
+                plugin.unwrap_or(name) != "charm"
+            })
+            .map(|(name, _)| name.as_str())
+            .collect()
+    }
 }
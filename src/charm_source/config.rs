@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use indexmap::IndexMap;
 
 use serde_derive::{Deserialize, Serialize};
 
@@ -17,14 +17,30 @@ pub enum ConfigOption {
     #[serde(rename = "int", rename_all = "kebab-case")]
     Integer { default: i64, description: String },
 
+    /// Float config option
+    #[serde(rename_all = "kebab-case")]
+    Float { default: f64, description: String },
+
     /// Boolean config option
     #[serde(rename_all = "kebab-case")]
     Boolean { default: bool, description: String },
 }
 
+impl ConfigOption {
+    /// The name of the option's type, as written in config.yaml
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            ConfigOption::String { .. } => "string",
+            ConfigOption::Integer { .. } => "int",
+            ConfigOption::Float { .. } => "float",
+            ConfigOption::Boolean { .. } => "boolean",
+        }
+    }
+}
+
 /// A charm's config.yaml file
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "kebab-case")]
 pub struct Config {
-    pub options: HashMap<String, ConfigOption>,
+    pub options: IndexMap<String, ConfigOption>,
 }
@@ -41,7 +41,6 @@ pub struct ResourceContainer {
     /// Reference for an entry in the resources field
     ///
     /// Specifies the oci-image resource used to create the container.
-    #[serde(default)]
     pub resource: String,
 
     /// List of mounted storages for this container
@@ -1,10 +1,10 @@
-use std::collections::HashMap;
+use indexmap::IndexMap;
 
 use serde_derive::{Deserialize, Serialize};
 
 use super::container::Container;
 use super::device::Device;
-use super::relation::Relation;
+use super::relation::{Relation, RelationRole};
 use super::resource::Resource;
 use super::storage::Storage;
 
@@ -43,52 +43,79 @@ pub struct Metadata {
     /// This field is required when the charm is targeting Kubernetes, where each of the specified
     /// containers will be created as sidecars to the charm in the same pod.
     #[serde(default)]
-    pub containers: HashMap<String, Container>,
+    pub containers: IndexMap<String, Container>,
 
     /// Additional resources that accompany the charm
     ///
     /// Each key represents the name of the resource
     #[serde(default)]
-    pub resources: HashMap<String, Resource>,
+    pub resources: IndexMap<String, Resource>,
 
     /// Map of relations provided by this charm
     ///
     /// Each key represents the name of the relation as known by this charm
     #[serde(default)]
-    pub provides: HashMap<String, Relation>,
+    pub provides: IndexMap<String, Relation>,
 
     /// Map of relations required by this charm
     ///
     /// Each key represents the name of the relation as known by this charm
     #[serde(default)]
-    pub requires: HashMap<String, Relation>,
+    pub requires: IndexMap<String, Relation>,
 
     /// Mutual relations between units/peers of this charm
     ///
     /// Each key represents the name of the relation as known by this charm
     #[serde(default)]
-    pub peer: HashMap<String, Relation>,
+    pub peer: IndexMap<String, Relation>,
 
     /// Storage requests for the charm
     ///
     /// Each key represents the name of the storage
     #[serde(default)]
-    pub storage: HashMap<String, Storage>,
+    pub storage: IndexMap<String, Storage>,
 
     /// Device requests for the charm, for example a GPU
     ///
     /// Each key represents the name of the device
     #[serde(default)]
-    pub devices: HashMap<String, Device>,
+    pub devices: IndexMap<String, Device>,
 
     /// Extra bindings for the charm
     ///
     /// For example binding extra network interfaces. Key only map, value must be blank. Key
     /// represents the name
     #[serde(default)]
-    pub extra_bindings: HashMap<String, ()>,
+    pub extra_bindings: IndexMap<String, ()>,
 
     /// If set, Juju magically determines that the charm is using v1 metadata
     #[serde(default)]
     pub series: Option<Vec<String>>,
 }
+
+impl Metadata {
+    /// Looks up a relation endpoint by name, along with which side of the relation it's on
+    pub fn endpoint(&self, name: &str) -> Option<(RelationRole, &Relation)> {
+        self.endpoints()
+            .find(|(n, _, _)| *n == name)
+            .map(|(_, role, rel)| (role, rel))
+    }
+
+    /// Iterates over all relation endpoints declared by the charm
+    pub fn endpoints(&self) -> impl Iterator<Item = (&str, RelationRole, &Relation)> {
+        let provides = self
+            .provides
+            .iter()
+            .map(|(n, r)| (n.as_str(), RelationRole::Provider, r));
+        let requires = self
+            .requires
+            .iter()
+            .map(|(n, r)| (n.as_str(), RelationRole::Requirer, r));
+        let peer = self
+            .peer
+            .iter()
+            .map(|(n, r)| (n.as_str(), RelationRole::Peer, r));
+
+        provides.chain(requires).chain(peer)
+    }
+}
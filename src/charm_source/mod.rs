@@ -1,34 +1,81 @@
+pub mod artifact;
 pub mod charmcraft;
 pub mod config;
 pub mod container;
 pub mod device;
 pub mod metadata;
+pub mod pack;
 pub mod relation;
 pub mod resource;
 pub mod storage;
 
-pub use charmcraft::{Base, BaseSpec, Charmcraft};
+pub use artifact::Artifact;
+pub use charmcraft::{Base, BaseSpec, Charmcraft, Platform};
 pub use config::{Config, ConfigOption};
 pub use container::{BaseContainer, Container, ContainerBase, ContainerMount, ResourceContainer};
+pub use device::Device;
 pub use metadata::Metadata;
-pub use relation::{Relation, RelationScope};
+pub use relation::{Relation, RelationRole, RelationScope};
 pub use resource::Resource;
 pub use storage::Storage;
 
 use std::collections::HashMap;
 use std::env::current_dir;
 use std::io::Read;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::from_utf8;
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
 
-use ex::fs::{read, File};
+use ex::fs::{copy, create_dir_all, read, remove_dir_all, rename, File};
+use ignore::WalkBuilder;
+use indexmap::IndexMap;
 use serde_derive::{Deserialize, Serialize};
 use serde_yaml::from_slice;
+use sha2::{Digest, Sha256};
 use zip::ZipArchive;
 
 use crate::charm_url::CharmURL;
 use crate::cmd;
 use crate::error::JujuError;
+use crate::paths;
+
+/// A charm uploaded to Charmhub by `CharmSource::upload`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Upload {
+    /// The charm, without a revision
+    pub charm: CharmURL,
+
+    /// The revision uploaded for each base and architecture the charm was built for
+    pub revisions: IndexMap<Platform, u32>,
+
+    /// The revision each OCI image resource was uploaded as
+    pub resources: IndexMap<String, String>,
+}
+
+impl Upload {
+    /// The revision uploaded for the given series and architecture
+    ///
+    /// Picks the first match, the same way as `Artifact::select`.
+    pub fn revision(&self, series: Option<&str>, arch: Option<&str>) -> Option<u32> {
+        self.revisions
+            .iter()
+            .find(|(platform, _)| platform.supports(series, arch))
+            .map(|(_, revision)| *revision)
+    }
+}
+
+/// Returns the lock for a build cache directory, shared by every thread in the process
+fn cache_lock(dir: &Path) -> Arc<Mutex<()>> {
+    static LOCKS: OnceLock<Mutex<HashMap<PathBuf, Arc<Mutex<()>>>>> = OnceLock::new();
+
+    LOCKS
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .entry(dir.to_path_buf())
+        .or_default()
+        .clone()
+}
 
 /// A charm, as represented by the source directory
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -107,46 +154,308 @@ impl CharmSource {
         }
     }
 
+    /// The path to the charm's source directory, or to its `.charm` file
+    pub fn path(&self) -> &Path {
+        &self.source
+    }
+
+    /// Hashes the files in the charm's source directory
+    ///
+    /// The hash covers each file's path relative to the source directory and its
+    /// contents, so it changes whenever a file is added, removed, renamed or edited.
+    /// Files matched by `.gitignore` or `.jujuignore` are skipped, as are version
+    /// control metadata and built `.charm` files.
+    pub fn source_hash(&self) -> Result<String, JujuError> {
+        let mut files = vec![];
+
+        let walker = WalkBuilder::new(&self.source)
+            .hidden(false)
+            .require_git(false)
+            .add_custom_ignore_filename(".jujuignore")
+            .sort_by_file_name(|a, b| a.cmp(b))
+            .filter_entry(|e| e.file_name() != ".git")
+            .build();
+
+        for entry in walker {
+            let entry = entry.map_err(std::io::Error::other)?;
+            let path = entry.path();
+            let is_file = entry.file_type().map(|t| t.is_file()).unwrap_or(false);
+
+            if is_file && path.extension() != Some("charm".as_ref()) {
+                files.push(path.to_path_buf());
+            }
+        }
+
+        let mut hasher = Sha256::new();
+
+        for file in files {
+            let relative = file.strip_prefix(&self.source).unwrap_or(&file);
+            let contents = read(&file)?;
+
+            hasher.update(relative.to_string_lossy().as_bytes());
+            hasher.update([0]);
+            hasher.update((contents.len() as u64).to_le_bytes());
+            hasher.update(&contents);
+        }
+
+        Ok(format!("sha256:{:x}", hasher.finalize()))
+    }
+
+    /// Every base that the charm runs on, across all of `bases`
+    pub fn run_on(&self) -> Vec<Base> {
+        self.charmcraft
+            .bases
+            .iter()
+            .flat_map(|spec| spec.run_on.iter().cloned())
+            .collect()
+    }
+
+    /// Pairs every base and architecture the charm runs on with the artifact that covers it
+    ///
+    /// See `Charmcraft::platforms`. Platforms that none of `artifacts` cover are
+    /// paired with `None`.
+    pub fn build_matrix<'a>(
+        &self,
+        artifacts: &'a [Artifact],
+    ) -> Vec<(Platform, Option<&'a Artifact>)> {
+        self.charmcraft
+            .platforms()
+            .into_iter()
+            .map(|platform| {
+                let artifact = artifacts.iter().find(|a| a.covers(&platform));
+                (platform, artifact)
+            })
+            .collect()
+    }
+
+    /// Lists the builds of this charm that are in the given directory
+    pub fn find_artifacts<P: AsRef<Path>>(&self, dir: P) -> Result<Vec<Artifact>, JujuError> {
+        let mut artifacts: Vec<_> =
+            Artifact::scan(&self.metadata.name, dir.as_ref(), &self.run_on())?
+                .into_iter()
+                .map(|(_, (artifact, _))| artifact)
+                .collect();
+        artifacts.sort_by(|a, b| a.path.cmp(&b.path));
+
+        Ok(artifacts)
+    }
+
+    /// The directory that builds of this charm from source with the given hash are cached in
+    fn cache_dir(&self, hash: &str) -> PathBuf {
+        let hash = hash.trim_start_matches("sha256:");
+
+        paths::charm_cache_dir(&self.metadata.name).join(format!("{}-{}", self.metadata.name, hash))
+    }
+
+    /// Build the charm, unless its source is unchanged since a previous build
+    ///
+    /// Built charms are cached under `paths::charm_cache_dir`, keyed on the hash of
+    /// their source from `CharmSource::source_hash`. Returns the cached copies of
+    /// the built charms.
+    ///
+    /// If `log` is set, `charmcraft`'s output is written there instead of to the terminal.
+    pub fn build_cached(
+        &self,
+        destructive_mode: bool,
+        log: Option<&Path>,
+    ) -> Result<Vec<Artifact>, JujuError> {
+        self.cached(&self.source_hash()?, |staging| {
+            for artifact in self.pack(destructive_mode, log)? {
+                copy(
+                    &artifact.path,
+                    staging.join(artifact.path.file_name().unwrap()),
+                )?;
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Returns the builds cached under `hash`, or caches the ones that `pack` writes
+    /// to the directory it's given
+    ///
+    /// Only one build for each `hash` runs at a time within this process, so that
+    /// applications sharing a source wait for the first build rather than racing it.
+    fn cached<F>(&self, hash: &str, pack: F) -> Result<Vec<Artifact>, JujuError>
+    where
+        F: FnOnce(&Path) -> Result<(), JujuError>,
+    {
+        let cached = self.cache_dir(hash);
+
+        let lock = cache_lock(&cached);
+        let _guard = lock.lock().unwrap_or_else(PoisonError::into_inner);
+
+        if let Some(artifacts) = self.cached_artifacts(&cached)? {
+            return Ok(artifacts);
+        }
+
+        // Build somewhere temporary first, so that a failed build or copy doesn't
+        // leave an incomplete build in the cache
+        let parent = cached.parent().unwrap();
+        create_dir_all(parent)?;
+        let staging = tempfile::Builder::new()
+            .prefix(".staging")
+            .tempdir_in(parent)?;
+
+        pack(staging.path())?;
+
+        // Another process may have cached the same build in the meantime
+        if let Some(artifacts) = self.cached_artifacts(&cached)? {
+            return Ok(artifacts);
+        }
+
+        if cached.exists() {
+            remove_dir_all(&cached)?;
+        }
+
+        if let Err(err) = rename(staging.path(), &cached) {
+            return match self.cached_artifacts(&cached)? {
+                Some(artifacts) => Ok(artifacts),
+                None => Err(err.into()),
+            };
+        }
+
+        self.find_artifacts(&cached)
+    }
+
+    /// The builds in a cache directory, if it exists and has any
+    fn cached_artifacts(&self, dir: &Path) -> Result<Option<Vec<Artifact>>, JujuError> {
+        if !dir.is_dir() {
+            return Ok(None);
+        }
+
+        let artifacts = self.find_artifacts(dir)?;
+
+        Ok(if artifacts.is_empty() {
+            None
+        } else {
+            Some(artifacts)
+        })
+    }
+
     /// Build the charm from its source directory
-    pub fn build(&self, destructive_mode: bool) -> Result<(), JujuError> {
-        let source = self.source.to_string_lossy();
-        let mut args = vec!["pack", "-p", &source];
+    ///
+    /// Returns the `.charm` files that were built, in the current directory.
+    pub fn build(&self, destructive_mode: bool) -> Result<Vec<Artifact>, JujuError> {
+        self.pack(destructive_mode, None)
+    }
+
+    /// Build the charm, writing `charmcraft`'s output to a log file
+    pub fn build_logged(
+        &self,
+        destructive_mode: bool,
+        log: &Path,
+    ) -> Result<Vec<Artifact>, JujuError> {
+        self.pack(destructive_mode, Some(log))
+    }
+
+    /// Runs `charmcraft pack`, and finds the `.charm` files it produced
+    ///
+    /// The files are taken from charmcraft's output if it was logged. Otherwise,
+    /// they're the builds of this charm in the output directory that are new or
+    /// have changed since the build started.
+    fn pack(&self, destructive_mode: bool, log: Option<&Path>) -> Result<Vec<Artifact>, JujuError> {
+        let name = &self.metadata.name;
+        let run_on = self.run_on();
+        let dir = current_dir()?;
+        let before = Artifact::scan(name, &dir, &run_on)?;
+
+        match log {
+            Some(log) => cmd::run_logged("charmcraft", &self.pack_args(destructive_mode), log)?,
+            None => cmd::run("charmcraft", &self.pack_args(destructive_mode))?,
+        }
+
+        if let Some(log) = log {
+            let output = String::from_utf8_lossy(&read(log)?).into_owned();
+            let artifacts = Artifact::from_output(name, &output, &dir, &run_on);
+
+            if !artifacts.is_empty() {
+                return Ok(artifacts);
+            }
+        }
+
+        let mut artifacts: Vec<_> = Artifact::scan(name, &dir, &run_on)?
+            .into_iter()
+            .filter(|(path, (_, modified))| match before.get(path) {
+                Some((_, previous)) => previous != modified,
+                None => true,
+            })
+            .map(|(_, (artifact, _))| artifact)
+            .collect();
+        artifacts.sort_by(|a, b| a.path.cmp(&b.path));
+
+        if artifacts.is_empty() {
+            return Err(JujuError::NoArtifacts(name.clone()));
+        }
+
+        Ok(artifacts)
+    }
+
+    fn pack_args(&self, destructive_mode: bool) -> Vec<String> {
+        let mut args = vec![
+            "pack".into(),
+            "-p".into(),
+            self.source.to_string_lossy().into(),
+        ];
 
         if destructive_mode {
-            args.push("--destructive-mode")
+            args.push("--destructive-mode".into())
         }
 
-        cmd::run("charmcraft", &args)
+        args
     }
 
+    /// Guesses where `charmcraft pack` put the built charm
+    ///
+    /// Only covers the first base and architecture, and assumes charmcraft's older
+    /// naming scheme. Use the artifacts returned by `CharmSource::build` instead.
+    #[deprecated(note = "use the artifacts returned by `CharmSource::build`")]
     pub fn artifact_path(&self) -> CharmURL {
         let mut path = current_dir().unwrap();
         let base = &self.charmcraft.bases[0].build_on[0];
         let arch = self
             .charmcraft
             .architectures
-            .get(0)
+            .first()
             .map(String::as_str)
             .unwrap_or("amd64");
 
-        path.push(&format!(
+        path.push(format!(
             "{}_{}-{}-{}.charm",
             self.metadata.name, base.name, base.channel, arch
         ));
         CharmURL::from_path(path)
     }
 
+    /// Builds the charm and uploads it to Charmhub, releasing it to the given channels
+    ///
+    /// Returns the charm URL with the revision of the last uploaded artifact.
     pub fn upload_charmhub(
         &self,
-        resources: &HashMap<String, String>,
+        resources: &IndexMap<String, String>,
         to: &[String],
         destructive_mode: bool,
     ) -> Result<String, JujuError> {
-        self.build(destructive_mode)?;
+        let upload = self.upload(resources, to, destructive_mode)?;
+        let revision = upload.revisions.values().last().copied();
+
+        Ok(upload.charm.with_revision(revision).to_string())
+    }
+
+    /// Builds the charm and uploads it to Charmhub, releasing it to the given channels
+    ///
+    /// Returns the revision that each artifact and OCI image resource was uploaded as.
+    pub fn upload(
+        &self,
+        resources: &IndexMap<String, String>,
+        to: &[String],
+        destructive_mode: bool,
+    ) -> Result<Upload, JujuError> {
+        let artifacts = self.build(destructive_mode)?;
 
         let resources = self.resources_with_defaults(resources)?;
 
-        let resources: Vec<_> = resources
+        let resource_revisions: IndexMap<_, _> = resources
             .iter()
             .filter_map(|(name, value)| {
                 let res = self.metadata.resources.get(name).expect("Must exist!");
@@ -173,39 +482,60 @@ impl CharmSource {
                         let output = String::from_utf8_lossy(&output);
                         let revision = output.lines().nth(1).unwrap().split(' ').next().unwrap();
 
-                        Some(format!("--resource={}:{}", name, revision))
+                        Some((name.clone(), revision.to_string()))
                     }
                     Resource::File { .. } => None,
                 }
             })
             .collect();
 
-        let args: Vec<_> = vec![
-            "upload".into(),
-            "--quiet".into(),
-            self.artifact_path().to_string(),
-        ]
-        .into_iter()
-        .chain(to.iter().map(|ch| format!("--release={}", ch)))
-        .chain(resources)
-        .collect();
+        // Each artifact is uploaded as its own revision, one per base
+        let mut revisions = IndexMap::new();
 
-        let mut output = cmd::get_output("charmcraft", &args)?;
-        output.drain(0..9);
-        output.truncate(output.iter().position(|&x| x == 0x20).unwrap());
-        let revision = from_utf8(&output).unwrap().parse::<u32>().unwrap();
+        for artifact in &artifacts {
+            let args: Vec<_> = vec![
+                "upload".into(),
+                "--quiet".into(),
+                artifact.path.to_string_lossy().to_string(),
+            ]
+            .into_iter()
+            .chain(to.iter().map(|ch| format!("--release={}", ch)))
+            .chain(
+                resource_revisions
+                    .iter()
+                    .map(|(name, rev)| format!("--resource={}:{}", name, rev)),
+            )
+            .collect();
+
+            let mut output = cmd::get_output("charmcraft", &args)?;
+            output.drain(0..9);
+            output.truncate(output.iter().position(|&x| x == 0x20).unwrap());
+            let revision = from_utf8(&output).unwrap().parse::<u32>().unwrap();
+
+            for base in &artifact.bases {
+                for arch in &base.architectures {
+                    let platform = Platform {
+                        name: base.name.clone(),
+                        channel: base.channel.clone(),
+                        arch: arch.clone(),
+                    };
+                    revisions.insert(platform, revision);
+                }
+            }
+        }
 
-        Ok(CharmURL::parse(&self.metadata.name)
-            .unwrap()
-            .with_revision(Some(revision))
-            .to_string())
+        Ok(Upload {
+            charm: CharmURL::parse(&self.metadata.name).unwrap(),
+            revisions,
+            resources: resource_revisions,
+        })
     }
 
     /// Merge default resources with resources given in e.g. a bundle.yaml
     pub fn resources_with_defaults(
         &self,
-        configured: &HashMap<String, String>,
-    ) -> Result<HashMap<String, String>, JujuError> {
+        configured: &IndexMap<String, String>,
+    ) -> Result<IndexMap<String, String>, JujuError> {
         self.metadata
             .resources
             .iter()
@@ -0,0 +1,388 @@
+//! Packing simple charms into `.charm` files without charmcraft
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use ex::fs::{create_dir_all, read, read_dir};
+use ignore::WalkBuilder;
+use serde_derive::Serialize;
+use serde_yaml::to_string;
+use sha2::{Digest, Sha256};
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+use super::{Artifact, Base, BaseSpec, CharmSource};
+use crate::error::JujuError;
+
+/// Runs the charm with the operator framework, the same as charmcraft's `dispatch`
+const DISPATCH: &str = "#!/bin/sh\n\
+JUJU_DISPATCH_PATH=\"${JUJU_DISPATCH_PATH:-$0}\" PYTHONPATH=lib:venv /usr/bin/env python3 ./src/charm.py\n";
+
+/// Entries at the top of the source directory that are never packed
+///
+/// Either they're generated while packing, or they're build output, in line with
+/// the defaults that charmcraft adds to `.jujuignore`.
+const SKIPPED: &[&str] = &[
+    "dispatch",
+    "manifest.yaml",
+    "venv",
+    "build",
+    "parts",
+    "prime",
+    "stage",
+];
+
+#[derive(Serialize)]
+struct Attribute {
+    name: &'static str,
+    result: &'static str,
+}
+
+#[derive(Serialize)]
+struct Analysis {
+    attributes: Vec<Attribute>,
+}
+
+/// The `manifest.yaml` file that charmcraft adds to built charms
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct Manifest {
+    analysis: Analysis,
+    charmcraft_started_at: String,
+    bases: Vec<Base>,
+}
+
+/// Formats seconds since the Unix epoch as an RFC 3339 timestamp
+fn format_timestamp(secs: u64) -> String {
+    let (days, rem) = ((secs / 86400) as i64, secs % 86400);
+
+    // Converts days since the epoch to a date in the proleptic Gregorian calendar
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+/// The permissions to give a file in the archive
+#[cfg(unix)]
+fn mode(path: &Path) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+
+    std::fs::metadata(path)
+        .map(|m| m.permissions().mode() & 0o777)
+        .unwrap_or(0o644)
+}
+
+#[cfg(not(unix))]
+fn mode(_path: &Path) -> u32 {
+    0o644
+}
+
+/// Maps a file within a wheel to where `pip install --target` would put it
+///
+/// Returns `None` for files that wouldn't be installed, such as scripts.
+fn wheel_path(name: &str) -> Option<String> {
+    match name.split_once('/') {
+        Some((first, rest)) if first.ends_with(".data") => match rest.split_once('/') {
+            Some(("purelib", path)) | Some(("platlib", path)) => Some(path.into()),
+            _ => None,
+        },
+        _ => Some(name.into()),
+    }
+}
+
+impl CharmSource {
+    /// Lists the files to pack from the source directory, as archive names and paths
+    ///
+    /// Everything in the source directory is packed, as charmcraft's charm plugin
+    /// does, apart from files matched by `.jujuignore`, hidden files at the top,
+    /// built charms, the entries in `SKIPPED` and Python bytecode caches.
+    fn pack_files(&self) -> Result<Vec<(String, PathBuf)>, JujuError> {
+        let walker = WalkBuilder::new(&self.source)
+            .standard_filters(false)
+            .add_custom_ignore_filename(".jujuignore")
+            .sort_by_file_name(|a, b| a.cmp(b))
+            .filter_entry(|e| {
+                let name = e.file_name().to_string_lossy();
+
+                match e.depth() {
+                    0 => true,
+                    1 => {
+                        !name.starts_with('.')
+                            && !name.ends_with(".charm")
+                            && !SKIPPED.contains(&name.as_ref())
+                    }
+                    _ => name != "__pycache__" && !name.ends_with(".pyc"),
+                }
+            })
+            .build();
+
+        let mut files = vec![];
+
+        for entry in walker {
+            let entry = entry.map_err(std::io::Error::other)?;
+
+            if entry.file_type().map(|t| t.is_file()).unwrap_or(false) {
+                let relative = entry.path().strip_prefix(&self.source).unwrap();
+                let name = relative
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+
+                files.push((name, entry.path().to_path_buf()));
+            }
+        }
+
+        Ok(files)
+    }
+
+    /// Whether the charm's `requirements.txt` lists any dependencies
+    fn has_requirements(&self) -> Result<bool, JujuError> {
+        let path = self.source.join("requirements.txt");
+
+        if !path.is_file() {
+            return Ok(false);
+        }
+
+        Ok(String::from_utf8_lossy(&read(path)?).lines().any(|line| {
+            let line = line.trim();
+            !line.is_empty() && !line.starts_with('#')
+        }))
+    }
+
+    /// The filename charmcraft would give a build for the given bases
+    fn pack_name(&self, bases: &[Base]) -> String {
+        let bases: Vec<_> = bases
+            .iter()
+            .map(|b| format!("{}-{}-{}", b.name, b.channel, b.architectures.join("-")))
+            .collect();
+
+        format!("{}_{}.charm", self.metadata.name, bases.join("_"))
+    }
+
+    /// Packs a single `.charm` file, for the run-on bases of one entry in `bases`
+    fn pack_spec(
+        &self,
+        spec: &BaseSpec,
+        files: &[(String, PathBuf)],
+        wheels: &[PathBuf],
+        out_dir: &Path,
+    ) -> Result<PathBuf, JujuError> {
+        let bases: Vec<_> = spec
+            .run_on
+            .iter()
+            .map(|b| Base {
+                architectures: self.charmcraft.architectures_for(b),
+                ..b.clone()
+            })
+            .collect();
+
+        let manifest = Manifest {
+            analysis: Analysis {
+                attributes: vec![
+                    Attribute {
+                        name: "language",
+                        result: "python",
+                    },
+                    Attribute {
+                        name: "framework",
+                        result: "operator",
+                    },
+                ],
+            },
+            charmcraft_started_at: format_timestamp(
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or(0),
+            ),
+            bases: bases.clone(),
+        };
+
+        let path = out_dir.join(self.pack_name(&bases));
+        let mut zip = ZipWriter::new(File::create(&path)?);
+        let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+        for (name, file) in files {
+            zip.start_file(name, options.unix_permissions(mode(file)))?;
+            zip.write_all(&read(file)?)?;
+        }
+
+        zip.start_file("manifest.yaml", options.unix_permissions(0o644))?;
+        zip.write_all(to_string(&manifest)?.as_bytes())?;
+
+        zip.start_file("dispatch", options.unix_permissions(0o755))?;
+        zip.write_all(DISPATCH.as_bytes())?;
+
+        let mut seen = HashSet::new();
+
+        for wheel in wheels {
+            let mut archive = ZipArchive::new(File::open(wheel)?)?;
+
+            for i in 0..archive.len() {
+                let file = archive.by_index(i)?;
+
+                if file.is_dir() {
+                    continue;
+                }
+
+                if let Some(target) = wheel_path(file.name()) {
+                    let target = format!("venv/{}", target);
+
+                    if seen.insert(target.clone()) {
+                        zip.raw_copy_file_rename(file, target)?;
+                    }
+                }
+            }
+        }
+
+        zip.finish()?;
+
+        Ok(path)
+    }
+
+    /// Packs the charm into `.charm` files in `out_dir`, without running charmcraft
+    ///
+    /// Works for Python operator charms that don't have any parts needing compilation.
+    /// One charm is packed for each entry in `bases`, the same as `charmcraft pack`.
+    /// Each contains the charm's source directory, a generated `manifest.yaml` and
+    /// `dispatch` script, and a `venv/` with the contents of every wheel in
+    /// `wheelhouse`, if given. Files matched by `.jujuignore` are left out. Fails if
+    /// the charm's `requirements.txt` lists dependencies but there are no wheels.
+    pub fn pack_native(
+        &self,
+        out_dir: &Path,
+        wheelhouse: Option<&Path>,
+    ) -> Result<Vec<Artifact>, JujuError> {
+        let name = &self.metadata.name;
+
+        if self.source.is_file() {
+            return Err(JujuError::CannotPackNatively(
+                name.clone(),
+                "it's already packed".into(),
+            ));
+        }
+
+        let compiled = self.charmcraft.compiled_parts();
+        if !compiled.is_empty() {
+            return Err(JujuError::CannotPackNatively(
+                name.clone(),
+                format!("parts need compiling: {}", compiled.join(", ")),
+            ));
+        }
+
+        let files = self.pack_files()?;
+        let wheels = wheels(wheelhouse)?;
+
+        // Without its dependencies, such as `ops`, the charm would fail on its first hook
+        if wheels.is_empty() && self.has_requirements()? {
+            return Err(JujuError::CannotPackNatively(
+                name.clone(),
+                "requirements.txt lists dependencies, but there are no wheels to install".into(),
+            ));
+        }
+
+        create_dir_all(out_dir)?;
+
+        let run_on = self.run_on();
+
+        self.charmcraft
+            .bases
+            .iter()
+            .map(|spec| {
+                let path = self.pack_spec(spec, &files, &wheels, out_dir)?;
+                Artifact::parse(name, &path, &run_on)
+                    .ok_or_else(|| JujuError::NoArtifacts(name.clone()))
+            })
+            .collect()
+    }
+
+    /// Packs the charm with `CharmSource::pack_native`, unless its source and the
+    /// wheels in `wheelhouse` are unchanged since a previous build
+    ///
+    /// Built charms are cached the same way as by `CharmSource::build_cached`, but
+    /// apart from builds by charmcraft.
+    pub fn pack_native_cached(
+        &self,
+        wheelhouse: Option<&Path>,
+    ) -> Result<Vec<Artifact>, JujuError> {
+        let mut hasher = Sha256::new();
+        hasher.update(self.source_hash()?.as_bytes());
+
+        for wheel in wheels(wheelhouse)? {
+            hasher.update(wheel.file_name().unwrap().to_string_lossy().as_bytes());
+            hasher.update(read(&wheel)?);
+        }
+
+        let hash = format!("native-{:x}", hasher.finalize());
+
+        self.cached(&hash, |staging| {
+            self.pack_native(staging, wheelhouse)?;
+            Ok(())
+        })
+    }
+}
+
+/// Lists the wheels in `wheelhouse`, if given, in a stable order
+fn wheels(wheelhouse: Option<&Path>) -> Result<Vec<PathBuf>, JujuError> {
+    let mut wheels = vec![];
+
+    if let Some(wheelhouse) = wheelhouse {
+        for entry in read_dir(wheelhouse)? {
+            let path = entry?.path();
+
+            if path.extension() == Some("whl".as_ref()) {
+                wheels.push(path);
+            }
+        }
+    }
+    wheels.sort();
+
+    Ok(wheels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_timestamp(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(format_timestamp(1_700_000_000), "2023-11-14T22:13:20Z");
+    }
+
+    #[test]
+    fn test_wheel_path() {
+        assert_eq!(wheel_path("ops/model.py"), Some("ops/model.py".into()));
+        assert_eq!(
+            wheel_path("ops-2.0.dist-info/RECORD"),
+            Some("ops-2.0.dist-info/RECORD".into())
+        );
+        assert_eq!(
+            wheel_path("foo-1.0.data/purelib/foo/bar.py"),
+            Some("foo/bar.py".into())
+        );
+        assert_eq!(wheel_path("foo-1.0.data/scripts/foo"), None);
+        assert_eq!(wheel_path("six.py"), Some("six.py".into()));
+    }
+}
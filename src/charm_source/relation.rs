@@ -1,16 +1,30 @@
 use serde_derive::{Deserialize, Serialize};
 
 /// Scope of a given relation
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
 #[serde(rename_all = "kebab-case")]
 pub enum RelationScope {
+    #[default]
     Global,
     Container,
 }
 
-impl Default for RelationScope {
-    fn default() -> Self {
-        Self::Global
+/// Which side of a relation an endpoint is on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RelationRole {
+    Provider,
+    Requirer,
+    Peer,
+}
+
+impl RelationRole {
+    /// Whether an endpoint with this role can be related to one with `other`
+    pub fn can_relate_to(self, other: RelationRole) -> bool {
+        matches!(
+            (self, other),
+            (RelationRole::Provider, RelationRole::Requirer)
+                | (RelationRole::Requirer, RelationRole::Provider)
+        )
     }
 }
 
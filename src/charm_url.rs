@@ -1,4 +1,5 @@
 use std::convert::TryFrom;
+use std::fmt;
 use std::path::PathBuf;
 use std::str::FromStr;
 
@@ -169,25 +170,23 @@ impl TryFrom<&str> for CharmURL {
     }
 }
 
-impl ToString for CharmURL {
-    fn to_string(&self) -> String {
-        let mut serialized = String::new();
-
+impl fmt::Display for CharmURL {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         if let Some(st) = &self.store {
-            serialized.push_str(&format!("{}:", st))
+            write!(f, "{}:", st)?;
         }
 
         if let Some(ns) = &self.namespace {
-            serialized.push_str(&format!("~{}/", ns))
+            write!(f, "~{}/", ns)?;
         }
 
-        serialized.push_str(&self.name);
+        f.write_str(&self.name)?;
 
         if let Some(rev) = &self.revision {
-            serialized.push_str(&format!("-{}", rev))
+            write!(f, "-{}", rev)?;
         }
 
-        serialized
+        Ok(())
     }
 }
 
@@ -208,6 +207,11 @@ impl<'de> Deserialize<'de> for CharmURL {
         use serde::de::Error;
         let s = String::deserialize(deserializer)?;
 
+        // Locally built charms are referred to by path, see `CharmURL::from_path`
+        if s.starts_with('/') || s.starts_with('.') {
+            return Ok(CharmURL::from_path(s));
+        }
+
         s.parse()
             .map_err(|err| Error::custom(format!("Error deserializing CharmURL: {}", err)))
     }
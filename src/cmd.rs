@@ -1,5 +1,7 @@
 use std::ffi::OsStr;
-use std::process::Command;
+use std::fs::File;
+use std::path::Path;
+use std::process::{Command, Stdio};
 
 use crate::error::JujuError;
 
@@ -29,6 +31,39 @@ pub fn run<S: AsRef<OsStr>>(cmd: &str, args: &[S]) -> Result<(), JujuError> {
     }
 }
 
+/// Runs a command with its stdout and stderr written to a log file instead of the terminal
+pub fn run_logged<S: AsRef<OsStr>>(cmd: &str, args: &[S], log: &Path) -> Result<(), JujuError> {
+    let stdout = File::create(log)?;
+    let stderr = stdout.try_clone()?;
+
+    let status = Command::new(cmd)
+        .args(args)
+        .env("CHARMCRAFT_DEVELOPER", "y")
+        .stdin(Stdio::null())
+        .stdout(stdout)
+        .stderr(stderr)
+        .spawn()
+        .map_err(|err| JujuError::SubcommandError(cmd.to_string(), err.to_string()))?
+        .wait()
+        .map_err(|err| JujuError::SubcommandError(cmd.to_string(), err.to_string()))?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(JujuError::SubcommandError(
+            format!(
+                "`{} {}`",
+                cmd,
+                args.iter()
+                    .map(|a| a.as_ref().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            ),
+            format!("{}, see {}", status, log.display()),
+        ))
+    }
+}
+
 pub fn get_output<S: AsRef<OsStr>>(cmd: &str, args: &[S]) -> Result<Vec<u8>, JujuError> {
     let output = Command::new(cmd)
         .args(args)
@@ -7,6 +7,8 @@ use serde_yaml::Error as YamlError;
 use thiserror::Error as ThisError;
 use zip::result::ZipError;
 
+use crate::bundle::BuildFailure;
+
 #[derive(Debug, ThisError)]
 pub enum JujuError {
     #[error("I/O error: {0}")]
@@ -41,4 +43,37 @@ pub enum JujuError {
 
     #[error("Error charm URL prefix: {0}")]
     MissingSourceError(String),
+
+    #[error("Placement `{1}` for {0} refers to a machine not defined in the bundle")]
+    UnknownMachine(String, String),
+
+    #[error("Invalid storage or device directive for {0}: {1}")]
+    InvalidDirective(String, String),
+
+    #[error("No matching endpoints found for relation `{0}`")]
+    NoMatchingRelation(String),
+
+    #[error("Relation `{0}` is ambiguous, it could use any of: {1}")]
+    AmbiguousRelation(String, String),
+
+    #[error("Couldn't include `{2}` in option `{1}` for {0}: {3}")]
+    IncludeError(String, String, String, #[source] IOError),
+
+    #[error("Timed out waiting for {0}")]
+    Timeout(String),
+
+    #[error("Couldn't find any built charms for {0}")]
+    NoArtifacts(String),
+
+    #[error("Can't pack {0} without charmcraft, as {1}")]
+    CannotPackNatively(String, String),
+
+    #[error("None of the charms built for {0} run on {1}")]
+    NoMatchingArtifact(String, String),
+
+    #[error("Source of {0} has changed since it was locked, expected {1} but found {2}")]
+    LockedSourceChanged(String, String, String),
+
+    #[error("Failed to build {} application(s):{}", .0.len(), crate::bundle::build::describe_failures(.0))]
+    BuildFailed(Vec<BuildFailure>),
 }
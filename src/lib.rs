@@ -7,4 +7,5 @@ pub mod error;
 pub mod local;
 pub mod paths;
 pub mod series;
+pub mod status;
 pub mod store;
@@ -1,7 +1,7 @@
 //! Parsing for `~/.local/share/juju/*` files
 
 pub use self::controller::ControllerYaml;
-pub use self::model::ModelYaml;
+pub use self::model::{ModelRef, ModelYaml};
 
 pub mod controller;
 pub mod model;
@@ -1,10 +1,13 @@
 use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
 
 use ex::fs::read;
 use serde_derive::Deserialize;
 use serde_yaml::from_slice;
 
 use crate::error::JujuError;
+use crate::local::ControllerYaml;
 use crate::paths::juju_data_dir;
 
 #[derive(Debug, Clone, Deserialize)]
@@ -78,3 +81,53 @@ impl ModelYaml {
         }
     }
 }
+
+/// A model on a particular controller, as passed to `juju -m controller:model`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModelRef {
+    pub controller: String,
+    pub model: String,
+}
+
+impl ModelRef {
+    pub fn new(controller: &str, model: &str) -> Self {
+        Self {
+            controller: controller.into(),
+            model: model.into(),
+        }
+    }
+
+    /// Finds a model, defaulting to the current controller and its current model
+    pub fn resolve(controller: Option<&str>, model: Option<&str>) -> Result<Self, JujuError> {
+        let controller = ControllerYaml::load()?.validate_name(controller)?;
+        let model = ModelYaml::load()?.validate_name(&controller, model)?;
+
+        Ok(Self { controller, model })
+    }
+
+    pub fn parse(input: &str) -> Result<Self, String> {
+        match input.split_once(':') {
+            Some((controller, model)) if !controller.is_empty() && !model.is_empty() => {
+                Ok(Self::new(controller, model))
+            }
+            _ => Err(format!(
+                "Couldn't parse model `{}`, expected `controller:model`",
+                input
+            )),
+        }
+    }
+}
+
+impl FromStr for ModelRef {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl fmt::Display for ModelRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.controller, self.model)
+    }
+}
@@ -1,3 +1,5 @@
+use std::fmt;
+
 use serde_derive::{Deserialize, Serialize};
 
 /// Type of charm and/or bundle
@@ -25,6 +27,15 @@ pub enum Series {
     Cosmic,
     Disco,
     Eoan,
+    Focal,
+    Groovy,
+    Hirsute,
+    Impish,
+    Jammy,
+    Kinetic,
+    Lunar,
+    Mantic,
+    Noble,
 
     // Windows
     Win2012hvr2,
@@ -42,3 +53,10 @@ pub enum Series {
     // CentOS
     Centos7,
 }
+
+impl fmt::Display for Series {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = serde_yaml::to_value(self).map_err(|_| fmt::Error)?;
+        f.write_str(value.as_str().unwrap_or_default())
+    }
+}
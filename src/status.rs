@@ -0,0 +1,182 @@
+//! Typed snapshot of a model, as output by `juju status --format yaml`
+//!
+//! Only the parts needed to compare a model against a bundle are parsed.
+
+use std::path::PathBuf;
+
+use ex::fs::read;
+use indexmap::IndexMap;
+use serde::{Deserialize, Deserializer};
+use serde_derive::{Deserialize, Serialize};
+use serde_yaml::{from_slice, Value};
+
+use crate::cmd;
+use crate::error::JujuError;
+
+/// A machine or container in the model
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct MachineStatus {
+    pub series: Option<String>,
+
+    #[serde(default)]
+    pub containers: IndexMap<String, MachineStatus>,
+}
+
+/// A unit of an application
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct UnitStatus {
+    /// The machine that the unit is on, which is unset for Kubernetes models
+    pub machine: Option<String>,
+
+    #[serde(default)]
+    pub subordinates: IndexMap<String, UnitStatus>,
+}
+
+/// The other side of a relation, as listed by an application
+///
+/// Juju 2.9 lists only the application name, while Juju 3 gives more detail.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum RelatedApplication {
+    Name(String),
+    #[serde(rename_all = "kebab-case")]
+    Detailed {
+        related_application: String,
+        interface: Option<String>,
+    },
+}
+
+impl RelatedApplication {
+    pub fn application(&self) -> &str {
+        match self {
+            RelatedApplication::Name(name) => name,
+            RelatedApplication::Detailed {
+                related_application,
+                ..
+            } => related_application,
+        }
+    }
+}
+
+/// An application deployed to the model
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct ApplicationStatus {
+    /// The charm, either as a full URL such as `cs:mysql-58` or just its name
+    pub charm: String,
+
+    pub charm_rev: Option<u32>,
+
+    pub charm_channel: Option<String>,
+
+    /// Where the charm came from, such as `charmhub` or `local`
+    pub charm_origin: Option<String>,
+
+    #[serde(default)]
+    pub exposed: bool,
+
+    #[serde(default)]
+    pub units: IndexMap<String, UnitStatus>,
+
+    /// Related applications, keyed by the name of this application's endpoint
+    #[serde(default)]
+    pub relations: IndexMap<String, Vec<RelatedApplication>>,
+
+    /// Applications that this one is a subordinate of
+    #[serde(default)]
+    pub subordinate_to: Vec<String>,
+}
+
+/// An application consumed from another model
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct RemoteApplicationStatus {
+    pub url: String,
+}
+
+/// The status of a model
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct Status {
+    #[serde(default)]
+    pub machines: IndexMap<String, MachineStatus>,
+
+    #[serde(default)]
+    pub applications: IndexMap<String, ApplicationStatus>,
+
+    /// SAAS entries, keyed by their name in this model
+    #[serde(default)]
+    pub application_endpoints: IndexMap<String, RemoteApplicationStatus>,
+}
+
+/// A resource used by an application
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct ResourceStatus {
+    pub name: String,
+
+    /// Where the resource came from, either `store` or `upload`
+    pub origin: Option<String>,
+
+    /// The resource's revision
+    ///
+    /// Juju 2.9 outputs this as a number, while Juju 3 outputs a string.
+    #[serde(default, deserialize_with = "deserialize_revision")]
+    pub revision: Option<String>,
+}
+
+fn deserialize_revision<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(match Value::deserialize(deserializer)? {
+        Value::Number(n) => Some(n.to_string()),
+        Value::String(s) => Some(s),
+        _ => None,
+    })
+}
+
+/// The resources of an application, as output by `juju resources --format yaml`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub struct ResourcesStatus {
+    #[serde(default)]
+    pub resources: Vec<ResourceStatus>,
+}
+
+impl ResourcesStatus {
+    /// Load a snapshot that was saved with `juju resources --format yaml`
+    pub fn load<P: Into<PathBuf>>(path: P) -> Result<Self, JujuError> {
+        Ok(from_slice(&read(path.into())?)?)
+    }
+
+    /// Get the current resources of an application, in the current model if `model` is `None`
+    pub fn current(model: Option<&str>, application: &str) -> Result<Self, JujuError> {
+        let mut args = vec!["resources", application, "--format", "yaml"];
+
+        if let Some(model) = model {
+            args.extend(&["-m", model]);
+        }
+
+        Ok(from_slice(&cmd::get_output("juju", &args)?)?)
+    }
+}
+
+impl Status {
+    /// Load a status snapshot that was saved with `juju status --format yaml`
+    pub fn load<P: Into<PathBuf>>(path: P) -> Result<Self, JujuError> {
+        Ok(from_slice(&read(path.into())?)?)
+    }
+
+    /// Get the current status of a model, or of the current model if `None`
+    pub fn current(model: Option<&str>) -> Result<Self, JujuError> {
+        let mut args = vec!["status", "--format", "yaml"];
+
+        if let Some(model) = model {
+            args.extend(&["-m", model]);
+        }
+
+        Ok(from_slice(&cmd::get_output("juju", &args)?)?)
+    }
+}
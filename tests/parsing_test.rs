@@ -1,27 +1,41 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::convert::TryInto;
 use std::fs;
-use std::path::PathBuf;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
 
+use indexmap::IndexMap;
 use serde_yaml::from_slice;
 
-use juju::bundle::{Annotations, Application, Bundle};
+use juju::bundle::graph::{Graph, Node, NodeKind};
+use juju::bundle::{
+    Annotations, Application, Bundle, BundleRelation, ContainerType, Machine, Offer, Placement,
+    Saas, Severity, Value,
+};
+use juju::bundle::{
+    BuildEvent, BuildOptions, BundleLock, LockedApplication, ModelState, RemoveOptions, Step,
+};
+use juju::charm_source::{Artifact, CharmSource, Platform, Upload};
+use juju::charm_url::CharmURL;
+use juju::error::JujuError;
 use juju::local::controller::{Controller, KubernetesPortForwardConfig, ProxyConfig};
-use juju::local::ControllerYaml;
+use juju::local::{ControllerYaml, ModelRef};
 use juju::series::Series;
+use juju::status::Status;
+
+fn relation(left: &str, right: &str) -> BundleRelation {
+    BundleRelation::new(left.parse().unwrap(), right.parse().unwrap())
+}
 
 #[test]
 fn parse_bundle() {
     let bundle = Bundle::load(PathBuf::from("tests/examples/bundle-basic.yaml")).unwrap();
-    let mut applications = HashMap::new();
+    let mut applications = IndexMap::new();
 
     applications.insert(
         "foo".to_string(),
         Application {
-            annotations: Some(Annotations {
-                gui_x: "0".into(),
-                gui_y: "0".into(),
-            }),
+            annotations: vec![("gui-x", "0"), ("gui-y", "0")].into_iter().collect(),
             source: Some("./foo".into()),
             charm: Some("cs:foo".try_into().unwrap()),
             scale: 1,
@@ -42,18 +56,1371 @@ fn parse_bundle() {
         bundle,
         Bundle {
             name: None,
+            annotations: Annotations::new(),
             applications,
             description: Some("An awesome bundle".to_string()),
-            relations: vec![vec!["foo", "bar"]
-                .into_iter()
-                .map(String::from)
-                .collect::<Vec<_>>()],
+            machines: IndexMap::new(),
+            relations: vec![relation("foo", "bar")],
             bundle: Some(Series::Kubernetes),
+            saas: IndexMap::new(),
             series: None,
         }
     );
 }
 
+#[test]
+fn parse_bundle_machines() {
+    let bundle = Bundle::load(PathBuf::from("tests/examples/bundle-machines.yaml")).unwrap();
+
+    let mut machines = IndexMap::new();
+    machines.insert(
+        "0".to_string(),
+        Machine {
+            annotations: vec![("rack", "a1")].into_iter().collect(),
+            constraints: Some("cores=2 mem=4G".try_into().unwrap()),
+            ..Default::default()
+        },
+    );
+    machines.insert(
+        "1".to_string(),
+        Machine {
+            base: Some("ubuntu@22.04".into()),
+            ..Default::default()
+        },
+    );
+    machines.insert("2".to_string(), Machine::default());
+
+    assert_eq!(bundle.machines, machines);
+    assert_eq!(bundle.annotations.get("owner"), Some("ops"));
+
+    let annotations = &bundle.applications["foo"].annotations;
+    assert_eq!(annotations.gui_position(), Some((300.0, -50.5)));
+    assert_eq!(annotations.get("colour"), Some("blue"));
+    assert_eq!(
+        bundle.applications["foo"].to,
+        vec![
+            Placement::Machine(0),
+            Placement::Container {
+                kind: ContainerType::Lxd,
+                machine: Some(1),
+            },
+        ]
+    );
+    bundle.validate_placement().unwrap();
+
+    // Machines should survive a round trip through `Bundle::save`
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("bundle.yaml");
+    bundle.save(&path).unwrap();
+    assert_eq!(Bundle::load(&path).unwrap(), bundle);
+}
+
+#[test]
+fn validate_placement_unknown_machine() {
+    let mut bundle = Bundle::load(PathBuf::from("tests/examples/bundle-machines.yaml")).unwrap();
+    bundle.machines.remove("1");
+
+    assert!(bundle.validate_placement().is_err());
+}
+
+#[test]
+fn parse_bundle_cmr() {
+    let bundle = Bundle::load(PathBuf::from("tests/examples/bundle-cmr.yaml")).unwrap();
+
+    let mut saas = IndexMap::new();
+    saas.insert(
+        "mysql".to_string(),
+        Saas {
+            url: "admin/database.mysql".into(),
+        },
+    );
+    assert_eq!(bundle.saas, saas);
+
+    let mut offers = IndexMap::new();
+    offers.insert(
+        "foo-api".to_string(),
+        Offer {
+            endpoints: vec!["api".into()],
+            acl: [
+                ("admin".to_string(), "admin".to_string()),
+                ("bob".to_string(), "consume".to_string()),
+            ]
+            .into(),
+        },
+    );
+    assert_eq!(bundle.applications["foo"].offers, offers);
+}
+
+#[test]
+fn limit_apps_keeps_saas_relations() {
+    let mut bundle = Bundle::load(PathBuf::from("tests/examples/bundle-cmr.yaml")).unwrap();
+    bundle.limit_apps(&["foo".into()], &[]).unwrap();
+
+    assert_eq!(
+        bundle.applications.keys().collect::<Vec<_>>(),
+        vec![&"foo".to_string()]
+    );
+    assert_eq!(bundle.relations, vec![relation("foo:db", "mysql:db")]);
+}
+
+#[test]
+fn load_multi_document_overlay() {
+    let bundle = Bundle::load(PathBuf::from("tests/examples/bundle-overlay.yaml")).unwrap();
+
+    let mut apps = bundle.applications.keys().cloned().collect::<Vec<_>>();
+    apps.sort();
+    assert_eq!(apps, vec!["baz", "foo"]);
+
+    let foo = &bundle.applications["foo"];
+    assert_eq!(foo.scale, 2);
+    assert_eq!(foo.options["verbose"], Value::Boolean(true));
+    assert_eq!(foo.options["port"], Value::Integer(8080));
+
+    // Relations to `bar` are removed along with it
+    assert_eq!(bundle.relations, vec![relation("foo", "baz")]);
+}
+
+#[test]
+fn load_with_overlays_resolves_sources() {
+    let bundle = Bundle::load_with_overlays(
+        "tests/examples/bundle-overlay.yaml",
+        &["tests/examples/overlays/dev.yaml"],
+    )
+    .unwrap();
+
+    assert_eq!(bundle.applications["foo"].scale, 3);
+    assert_eq!(
+        bundle.applications["foo"].options["port"],
+        Value::Integer(8080)
+    );
+
+    let expected = fs::canonicalize("tests/examples/overlays")
+        .unwrap()
+        .join("./qux");
+    assert_eq!(
+        bundle.applications["qux"].source,
+        Some(expected.to_string_lossy().to_string())
+    );
+    assert_eq!(
+        bundle.relations,
+        vec![relation("foo", "baz"), relation("foo", "qux")]
+    );
+}
+
+#[test]
+fn apply_typed_overlay() {
+    let mut bundle = Bundle::load(PathBuf::from("tests/examples/bundle-basic.yaml")).unwrap();
+
+    let mut overlay = Bundle::default();
+    overlay.applications.insert(
+        "foo".into(),
+        Application {
+            options: [("port".to_string(), Value::Integer(8080))].into(),
+            ..Default::default()
+        },
+    );
+    overlay.relations.push(relation("foo", "baz"));
+
+    bundle.apply_overlay(&overlay).unwrap();
+
+    let foo = &bundle.applications["foo"];
+    assert_eq!(foo.scale, 1);
+    assert_eq!(foo.charm, Some("cs:foo".try_into().unwrap()));
+    assert_eq!(foo.options["port"], Value::Integer(8080));
+    assert_eq!(bundle.bundle, Some(Series::Kubernetes));
+    assert_eq!(bundle.relations.len(), 2);
+}
+
+#[test]
+fn apply_overlay_defaults() {
+    let base = "applications:\n  foo:\n    charm: cs:foo\n    num_units: 2\n    expose: true\n    trust: true\n  bar:\n    charm: cs:bar\nrelations:\n  - [foo, bar]\n";
+    let overlay = "applications:\n  foo:\n    num_units: 0\n    expose: false\n    trust: false\n";
+
+    // A typed overlay can't express fields set back to their defaults
+    let mut bundle: Bundle = serde_yaml::from_str(base).unwrap();
+    bundle
+        .apply_overlay(&serde_yaml::from_str(overlay).unwrap())
+        .unwrap();
+    let foo = &bundle.applications["foo"];
+    assert_eq!((foo.scale, foo.expose, foo.trust), (2, true, true));
+
+    let mut bundle: Bundle = serde_yaml::from_str(base).unwrap();
+    bundle
+        .apply_overlay_yaml(&format!("{}  bar:\n", overlay))
+        .unwrap();
+    let foo = &bundle.applications["foo"];
+    assert_eq!((foo.scale, foo.expose, foo.trust), (0, false, false));
+    assert_eq!(
+        bundle.applications.keys().collect::<Vec<_>>(),
+        vec![&"foo".to_string()]
+    );
+    assert!(bundle.relations.is_empty());
+}
+
+#[test]
+fn save_preserving_layout() {
+    let path = "tests/examples/bundle-comments.yaml";
+    let original = fs::read_to_string(path).unwrap();
+    let mut bundle = Bundle::load(path).unwrap();
+
+    // Unchanged bundles are written out exactly as they were
+    assert_eq!(bundle.to_string_preserving(&original).unwrap(), original);
+
+    bundle.applications["web"].scale = 3;
+    bundle.applications["web"]
+        .options
+        .insert("debug".into(), Value::Boolean(true));
+    bundle.applications.shift_remove("cache");
+    bundle.applications.insert(
+        "proxy".into(),
+        Application {
+            charm: Some("cs:haproxy".try_into().unwrap()),
+            scale: 1,
+            ..Default::default()
+        },
+    );
+    bundle
+        .limit_apps(&["web".into(), "db".into(), "proxy".into()], &[])
+        .unwrap();
+
+    let saved = bundle.to_string_preserving(&original).unwrap();
+    // Only the changed fields are rewritten, keeping comments on the rest of `web`
+    let expected = original
+        .replace("    num_units: 2\n", "    num_units: 3\n")
+        .replace("      debug: false\n", "      debug: true\n")
+        .replace(
+            "  cache:\n    charm: cs:memcached\n    num_units: 1\n",
+            "  proxy:\n    charm: \"cs:haproxy\"\n    num_units: 1\n",
+        )
+        .replace("  - [web, db]\n  - [web, cache]\n", "  - - web\n    - db\n");
+
+    assert_eq!(saved, expected);
+    assert_eq!(serde_yaml::from_str::<Bundle>(&saved).unwrap(), bundle);
+}
+
+#[test]
+fn save_preserving_block_scalars() {
+    let original = "applications:\n  web:\n    charm: cs:nginx\n    options:\n      script: |\n        run\n        # done\n      # Remove me\n      debug: true\n      port: 80\nrelations: []\n";
+    let mut bundle: Bundle = serde_yaml::from_str(original).unwrap();
+
+    bundle.applications["web"].options.shift_remove("debug");
+
+    // The scalar's last lines stay, while the removed option's comment goes with it
+    let saved = bundle.to_string_preserving(original).unwrap();
+    assert_eq!(
+        saved,
+        original.replace("      # Remove me\n      debug: true\n", "")
+    );
+    assert_eq!(serde_yaml::from_str::<Bundle>(&saved).unwrap(), bundle);
+}
+
+#[test]
+fn save_preserving_exported_layout() {
+    let path = "tests/examples/bundle-export.yaml";
+    let original = fs::read_to_string(path).unwrap();
+    let mut bundle = Bundle::load(path).unwrap();
+
+    assert_eq!(bundle.to_string_preserving(&original).unwrap(), original);
+
+    bundle.applications["web"].scale = 3;
+    bundle.relations.push(relation("web:cache", "cache:cache"));
+
+    let saved = bundle.to_string_preserving(&original).unwrap();
+    let expected = original
+        .replace("    num_units: 2\n", "    num_units: 3\n")
+        .replace(
+            "relations:\n- - web:db\n  - db:db\n",
+            "relations:\n  - - \"web:db\"\n    - \"db:db\"\n  - - \"web:cache\"\n    - \"cache:cache\"\n",
+        );
+
+    assert_eq!(saved, expected);
+    assert_eq!(serde_yaml::from_str::<Bundle>(&saved).unwrap(), bundle);
+}
+
+#[test]
+fn diff_bundles() {
+    let old = Bundle::load("tests/examples/bundle-comments.yaml").unwrap();
+    let mut new = old.clone();
+
+    assert!(old.diff(&new).is_empty());
+
+    let web = &mut new.applications["web"];
+    web.charm = Some("cs:web-2".try_into().unwrap());
+    web.scale = 3;
+    web.options.insert("port".into(), Value::Integer(80));
+    web.options.insert("verbose".into(), Value::Boolean(true));
+    web.resources.insert("image".into(), "2".into());
+    new.applications.shift_remove("cache");
+    new.applications
+        .insert("proxy".into(), Application::default());
+    new.relations = vec![relation("db", "web"), relation("proxy", "web")];
+
+    let diff = old.diff(&new);
+
+    assert_eq!(diff.added_applications, vec!["proxy"]);
+    assert_eq!(diff.removed_applications, vec!["cache"]);
+    assert_eq!(
+        diff.changed_applications.keys().collect::<Vec<_>>(),
+        vec!["web"]
+    );
+    assert_eq!(diff.added_relations, vec![relation("proxy", "web")]);
+    assert_eq!(diff.removed_relations, vec![relation("web", "cache")]);
+
+    assert_eq!(
+        diff.to_string(),
+        "Added applications:\n  \
+           + proxy\n\
+         Removed applications:\n  \
+           - cache\n\
+         Changed applications:\n  \
+           web:\n    \
+             charm: cs:web -> cs:web-2\n    \
+             scale: 2 -> 3\n    \
+             option port: 8080 -> 80\n    \
+             option verbose: (unset) -> true\n    \
+             resource image: (unset) -> 2\n\
+         Added relations:\n  \
+           + proxy web\n\
+         Removed relations:\n  \
+           - web cache\n"
+    );
+}
+
+#[test]
+fn deploy_changes() {
+    let bundle = Bundle::load("tests/examples/bundle-machines.yaml").unwrap();
+    let changes = bundle.changes(&ModelState::default());
+
+    let described: Vec<_> = changes.iter().map(|c| format!("{}: {}", c.id, c)).collect();
+    assert_eq!(
+        described,
+        vec![
+            "addCharm-0: upload charm cs:foo for series bionic",
+            "deploy-1: deploy application foo using cs:foo",
+            "setAnnotations-2: set annotations for foo",
+            "addCharm-3: upload charm cs:bar for series bionic",
+            "deploy-4: deploy application bar using cs:bar",
+            "addMachines-5: add new machine 0",
+            "setAnnotations-6: set annotations for new machine 0",
+            "addMachines-7: add new machine 1",
+            "addMachines-8: add new machine 2",
+            "addRelation-9: add relation foo - bar",
+            "addUnit-10: add unit foo/0 to new machine 0",
+            "addMachines-11: add lxd container on new machine 1",
+            "addUnit-12: add unit foo/1 to lxd container on new machine 1",
+            "addMachines-13: add new machine",
+            "addUnit-14: add unit bar/0 to new machine",
+        ]
+    );
+
+    assert_eq!(changes[12].requires, vec!["deploy-1", "addMachines-11"]);
+    assert_eq!(
+        changes[12].step,
+        Step::AddUnit {
+            application: "foo".into(),
+            to: Some("$addMachines-11".into()),
+        }
+    );
+
+    // Deploying on top of an existing model only adds what's missing
+    let status = Status::load("tests/examples/status.yaml").unwrap();
+    let mut bundle = bundle;
+    bundle.applications["foo"].annotations = Annotations::new();
+    bundle.applications["foo"].charm = Some("cs:foo-4".try_into().unwrap());
+
+    let described: Vec<_> = bundle
+        .changes(&ModelState::from(&status))
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    assert_eq!(
+        described,
+        vec![
+            "upload charm cs:foo-4 for series bionic",
+            "upgrade foo to use charm cs:foo-4",
+            "upload charm cs:bar for series bionic",
+            "deploy application bar using cs:bar",
+            "add new machine 1",
+            "add new machine 2",
+            "add lxd container on new machine 1",
+            "add unit foo/1 to lxd container on new machine 1",
+            "add new machine",
+            "add unit bar/0 to new machine",
+        ]
+    );
+}
+
+#[test]
+fn deploy_changes_round_robin() {
+    let bundle: Bundle = serde_yaml::from_str(
+        "applications:\n  foo:\n    charm: cs:foo\n    num_units: 2\n  bar:\n    charm: cs:bar\n    num_units: 3\n    to: [foo]\n",
+    )
+    .unwrap();
+
+    let described: Vec<_> = bundle
+        .changes(&ModelState::default())
+        .iter()
+        .map(ToString::to_string)
+        .filter(|c| c.starts_with("add unit"))
+        .collect();
+    assert_eq!(
+        described,
+        vec![
+            "add unit foo/0 to new machine",
+            "add unit foo/1 to new machine",
+            "add unit bar/0 to the machine of unit foo/0",
+            "add unit bar/1 to the machine of unit foo/1",
+            "add unit bar/2 to the machine of unit foo/0",
+        ]
+    );
+}
+
+#[test]
+fn render_script() {
+    let mut bundle = Bundle::load("tests/examples/bundle-machines.yaml").unwrap();
+    let foo = &mut bundle.applications["foo"];
+    foo.scale = 3;
+    foo.channel = Some("edge".into());
+    foo.constraints = Some("mem=4G".try_into().unwrap());
+    foo.options
+        .insert("motd".into(), Value::String("hello world".into()));
+    foo.resources.insert("image".into(), "2".into());
+    foo.options.insert("unset".into(), Value::None);
+    foo.expose = true;
+    bundle.applications["bar"].trust = true;
+
+    // Principals without units get `--num-units=0`, but subordinates never do
+    for name in &["logger", "idle"] {
+        bundle.applications.insert(
+            name.to_string(),
+            Application {
+                charm: Some(format!("cs:{}", name).as_str().try_into().unwrap()),
+                ..Default::default()
+            },
+        );
+    }
+    // Units placed alongside other units can't be placed from the CLI, which is noted
+    bundle.applications.insert(
+        "sidecar".into(),
+        Application {
+            charm: Some("cs:sidecar".try_into().unwrap()),
+            scale: 3,
+            to: vec!["foo/1".parse().unwrap(), "foo".parse().unwrap()],
+            ..Default::default()
+        },
+    );
+    let charms = load_charms();
+
+    assert_eq!(
+        bundle.to_script(true, &charms),
+        "#!/bin/sh\n\
+         set -e\n\
+         \n\
+         juju add-machine --series=bionic '--constraints=cores=2 mem=4G'\n\
+         juju add-machine --series=bionic\n\
+         juju add-machine --series=bionic\n\
+         juju deploy cs:foo foo --channel=edge --constraints=mem=4G '--config=motd=hello world' \
+         --resource=image=2 --series=bionic --to=0\n\
+         juju deploy cs:bar bar --series=bionic\n\
+         juju deploy cs:logger logger --series=bionic\n\
+         juju deploy cs:idle idle --series=bionic --num-units=0\n\
+         # Can't place sidecar/0 with `--to=foo/1`, so it goes on a new machine instead\n\
+         juju deploy cs:sidecar sidecar --series=bionic\n\
+         juju add-unit foo --to=lxd:1\n\
+         juju add-unit foo --num-units=1\n\
+         # Can't place sidecar/1 with `--to=foo`, so it goes on a new machine instead\n\
+         # Can't place sidecar/2 with `--to=foo`, so it goes on a new machine instead\n\
+         juju add-unit sidecar --num-units=2\n\
+         juju integrate foo bar\n\
+         juju expose foo\n\
+         juju trust bar\n"
+    );
+}
+
+#[test]
+fn removal_commands() {
+    let bundle = Bundle::load("tests/examples/bundle-cmr.yaml").unwrap();
+    let status = Status::load("tests/examples/status.yaml").unwrap();
+    let model: ModelRef = "lxd:default".parse().unwrap();
+
+    let opts = RemoveOptions {
+        destroy_storage: true,
+        ..Default::default()
+    };
+
+    assert_eq!(
+        bundle.removal_commands(&model, &status, &opts),
+        vec![
+            vec![
+                "remove-application",
+                "-m",
+                "lxd:default",
+                "--destroy-storage",
+                "foo"
+            ],
+            vec!["remove-saas", "-m", "lxd:default", "mysql"],
+        ]
+    );
+
+    assert!(bundle
+        .removal_commands(&model, &Status::default(), &opts)
+        .is_empty());
+    assert!("default".parse::<ModelRef>().is_err());
+
+    // SAAS entries that other applications still use are kept
+    let status = Status::load("tests/examples/status-shared-saas.yaml").unwrap();
+    let opts = RemoveOptions {
+        no_prompt: true,
+        ..Default::default()
+    };
+
+    assert_eq!(
+        bundle.removal_commands(&model, &status, &opts),
+        vec![vec![
+            "remove-application",
+            "-m",
+            "lxd:default",
+            "--no-prompt",
+            "foo",
+            "bar"
+        ]]
+    );
+}
+
+#[test]
+fn lock_bundle() {
+    let dir = tempfile::tempdir().unwrap();
+    let bundle_path = dir.path().join("bundle.yaml");
+
+    let mut bundle = Bundle::load("tests/examples/bundle-machines.yaml").unwrap();
+    let foo = &mut bundle.applications["foo"];
+    foo.charm = Some("cs:foo-7".parse().unwrap());
+    foo.channel = Some("stable".into());
+    foo.resources.insert("image".into(), "3".into());
+    bundle.applications["bar"].charm =
+        Some(CharmURL::from_path("/tmp/bar_ubuntu-20.04-amd64.charm"));
+
+    bundle.write_lock(bundle_path.to_str().unwrap()).unwrap();
+    let lock = BundleLock::load(BundleLock::path_for(&bundle_path)).unwrap();
+    assert_eq!(lock, bundle.lock());
+    assert_eq!(
+        lock.applications["bar"].charm,
+        Some(CharmURL::from_path("/tmp/bar_ubuntu-20.04-amd64.charm"))
+    );
+
+    let mut floating = Bundle::load("tests/examples/bundle-machines.yaml").unwrap();
+    floating.applications["foo"].channel = Some("edge".into());
+    floating
+        .applications
+        .insert("baz".into(), Application::default());
+    floating
+        .apply_lock(&lock, bundle_path.to_str().unwrap())
+        .unwrap();
+
+    let foo = &floating.applications["foo"];
+    assert_eq!(foo.charm, Some("cs:foo-7".parse().unwrap()));
+    assert_eq!(foo.channel.as_deref(), Some("stable"));
+    assert_eq!(foo.resources["image"], "3");
+    assert_eq!(floating.applications["baz"], Application::default());
+
+    assert_eq!(LockedApplication::new(&Application::default()), None);
+}
+
+#[test]
+fn lock_deployed_revisions() {
+    use juju::status::ResourcesStatus;
+
+    let bundle = Bundle::load("tests/examples/bundle-cmr.yaml").unwrap();
+    let status = Status::load("tests/examples/status-shared-saas.yaml").unwrap();
+    let resources = [(
+        "foo".to_string(),
+        ResourcesStatus::load("tests/examples/resources.yaml").unwrap(),
+    )]
+    .into();
+
+    let lock = bundle.lock_status(&status, &resources);
+
+    // Floating charms are pinned to what's deployed, along with store resources
+    let foo = &lock.applications["foo"];
+    assert_eq!(foo.charm, Some("cs:foo".parse().unwrap()));
+    assert_eq!(foo.revision, Some(3));
+    assert_eq!(foo.channel.as_deref(), Some("latest/stable"));
+    assert_eq!(
+        foo.resources,
+        [("image", "5"), ("schema", "4")]
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect::<IndexMap<_, _>>()
+    );
+    assert_eq!(lock.applications["bar"].revision, Some(1));
+
+    // Revisions are pinned apart from the charm URL, which Juju 3 requires
+    let mut pinned = bundle.clone();
+    pinned.apply_lock(&lock, "bundle.yaml").unwrap();
+    assert_eq!(
+        pinned.applications["foo"].charm,
+        Some("cs:foo".parse().unwrap())
+    );
+    assert_eq!(pinned.applications["foo"].revision, Some(3));
+    assert!(pinned.applications["foo"]
+        .deploy_args("foo")
+        .contains(&"--revision=3".to_string()));
+
+    let reloaded: Bundle = serde_yaml::from_str(&serde_yaml::to_string(&pinned).unwrap()).unwrap();
+    assert_eq!(reloaded.applications["foo"].revision, Some(3));
+
+    // Applications that aren't deployed are recorded as they are
+    let lock = bundle.lock_status(&Status::default(), &resources);
+    assert_eq!(lock, bundle.lock());
+}
+
+/// Copies one of the example charms' source into a new directory
+fn copy_charm(name: &str, dir: &Path) {
+    fs::create_dir_all(dir).unwrap();
+    for entry in fs::read_dir(PathBuf::from("tests/examples/charms").join(name)).unwrap() {
+        let entry = entry.unwrap();
+        fs::copy(entry.path(), dir.join(entry.file_name())).unwrap();
+    }
+}
+
+/// Points the charm build cache at a directory shared by every test
+fn charm_cache() -> PathBuf {
+    let cache = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("charm-cache");
+    std::env::set_var("CHARM_CACHE_DIR", &cache);
+    cache
+}
+
+/// Caches a fake build of the given charm in the shared charm build cache
+fn cache_build(charm: &CharmSource) -> PathBuf {
+    let cache = charm_cache();
+
+    let hash = charm.source_hash().unwrap();
+    let cached = cache.join(format!(
+        "{}-{}",
+        charm.metadata.name,
+        hash.trim_start_matches("sha256:")
+    ));
+    fs::create_dir_all(&cached).unwrap();
+
+    let artifact = cached.join(format!("{}_ubuntu-20.04-amd64.charm", charm.metadata.name));
+    fs::write(&artifact, "zip").unwrap();
+    artifact
+}
+
+#[test]
+fn charm_source_hash() {
+    let dir = tempfile::tempdir().unwrap();
+    copy_charm("web", dir.path());
+
+    let charm = CharmSource::load(dir.path()).unwrap();
+    let hash = charm.source_hash().unwrap();
+    assert!(hash.starts_with("sha256:"));
+
+    // Built artifacts don't count as source
+    fs::write(dir.path().join("web.charm"), "zip").unwrap();
+    assert_eq!(charm.source_hash().unwrap(), hash);
+
+    fs::write(dir.path().join("README.md"), "docs").unwrap();
+    assert_ne!(charm.source_hash().unwrap(), hash);
+
+    // Ignored files don't count either
+    fs::write(dir.path().join(".gitignore"), "build/\n").unwrap();
+    fs::write(dir.path().join(".jujuignore"), "*.log\n").unwrap();
+    let hash = charm.source_hash().unwrap();
+
+    fs::create_dir(dir.path().join("build")).unwrap();
+    fs::write(dir.path().join("build/output"), "built").unwrap();
+    fs::write(dir.path().join("debug.log"), "log").unwrap();
+    assert_eq!(charm.source_hash().unwrap(), hash);
+
+    // A cached build for this hash is used instead of running charmcraft
+    let cached = cache_build(&charm);
+    let artifacts = charm.build_cached(false, None).unwrap();
+    assert_eq!(artifacts.len(), 1);
+    assert_eq!(artifacts[0].path, cached);
+    assert_eq!(artifacts[0].bases[0].channel, "20.04");
+    assert_eq!(artifacts[0].bases[0].architectures, vec!["amd64"]);
+}
+
+#[test]
+fn build_failure_summary() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("bundle.yaml");
+
+    let mut bundle = Bundle::default();
+    bundle
+        .applications
+        .insert("nothing".into(), Application::default());
+    bundle.applications.insert(
+        "missing".into(),
+        Application {
+            source: Some("./missing".into()),
+            ..Default::default()
+        },
+    );
+    bundle.applications.insert(
+        "store".into(),
+        Application {
+            charm: Some("cs:store".parse().unwrap()),
+            ..Default::default()
+        },
+    );
+    let original = bundle.clone();
+
+    let events = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+    let recorded = events.clone();
+    let opts = BuildOptions {
+        jobs: Some(2),
+        progress: Some(Box::new(move |event| {
+            let description = match event {
+                BuildEvent::Started(name) => format!("started {}", name),
+                BuildEvent::Built(name, _) => format!("built {}", name),
+                BuildEvent::Failed(name, _) => format!("failed {}", name),
+            };
+            recorded.lock().unwrap().push(description);
+        })),
+        ..Default::default()
+    };
+
+    let failures = match bundle.build_with(path.to_str().unwrap(), None, &opts) {
+        Err(JujuError::BuildFailed(failures)) => failures,
+        other => panic!("Expected build failures, got {:?}", other),
+    };
+
+    let names: Vec<_> = failures.iter().map(|f| f.application.as_str()).collect();
+    assert_eq!(names, vec!["nothing", "missing"]);
+    assert!(matches!(
+        failures[0].error,
+        JujuError::MissingSourceError(_)
+    ));
+
+    let mut events = events.lock().unwrap().clone();
+    events.sort();
+    assert_eq!(
+        events,
+        vec!["failed missing", "failed nothing", "started missing"]
+    );
+
+    assert_eq!(bundle, original);
+    assert!(!BundleLock::path_for(&path).exists());
+}
+
+#[test]
+fn build_keep_going() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("bundle.yaml");
+    copy_charm("web", &dir.path().join("web"));
+    let cached = cache_build(&CharmSource::load(dir.path().join("web")).unwrap());
+
+    let mut bundle = Bundle::default();
+    bundle
+        .applications
+        .insert("web".into(), Application::default());
+    bundle.applications.insert(
+        "broken".into(),
+        Application {
+            charm: Some("cs:broken".parse().unwrap()),
+            source: Some("./missing".into()),
+            ..Default::default()
+        },
+    );
+
+    let opts = BuildOptions {
+        keep_going: true,
+        write_lock: true,
+        ..Default::default()
+    };
+    let report = bundle
+        .build_with(path.to_str().unwrap(), None, &opts)
+        .unwrap();
+
+    assert!(!report.is_success());
+    assert_eq!(report.failed.len(), 1);
+    assert_eq!(report.failed[0].application, "broken");
+    assert_eq!(report.built.len(), 1);
+    assert_eq!(report.built["web"], CharmURL::from_path(&cached));
+
+    let web = &bundle.applications["web"];
+    assert_eq!(web.charm, Some(CharmURL::from_path(&cached)));
+    assert_eq!(web.resources["web-image"], "web:latest");
+    assert_eq!(
+        bundle.applications["broken"].charm,
+        Some("cs:broken".parse().unwrap())
+    );
+
+    // Only what was actually built is locked, by its source rather than the cached build
+    let lock = BundleLock::load(BundleLock::path_for(&path)).unwrap();
+    let names: Vec<_> = lock.applications.keys().collect();
+    assert_eq!(names, vec!["web"]);
+    assert_eq!(lock.applications["web"].charm, None);
+    assert_eq!(lock.applications["web"].source.as_deref(), Some("./web"));
+    assert!(lock.applications["web"].source_hash.is_some());
+
+    let mut locked = Bundle::default();
+    locked.applications.insert(
+        "web".into(),
+        Application {
+            charm: Some("cs:web".parse().unwrap()),
+            ..Default::default()
+        },
+    );
+    locked.apply_lock(&lock, path.to_str().unwrap()).unwrap();
+    assert_eq!(locked.applications["web"].charm, None);
+    assert_eq!(locked.applications["web"].source.as_deref(), Some("./web"));
+
+    // Charms that were uploaded to Charmhub are deployed from there instead
+    let mut uploaded = lock.clone();
+    uploaded.applications["web"].charm = Some("web-3".parse().unwrap());
+    let mut published = Bundle::default();
+    published
+        .applications
+        .insert("web".into(), Application::default());
+    published
+        .apply_lock(&uploaded, path.to_str().unwrap())
+        .unwrap();
+    assert_eq!(
+        published.applications["web"].charm,
+        Some("web-3".parse().unwrap())
+    );
+    assert_eq!(published.applications["web"].source, None);
+
+    // Changing the source after locking it is caught
+    fs::write(dir.path().join("web/README.md"), "changed").unwrap();
+    assert!(matches!(
+        Bundle::default().apply_lock(&lock, path.to_str().unwrap()),
+        Ok(())
+    ));
+    assert!(matches!(
+        locked.apply_lock(&lock, path.to_str().unwrap()),
+        Err(JujuError::LockedSourceChanged(..))
+    ));
+
+    // Nothing is pinned if any source has changed
+    let mut mixed = BundleLock::default();
+    mixed.applications.insert(
+        "api".into(),
+        LockedApplication::new(&Application {
+            charm: Some("cs:api-2".parse().unwrap()),
+            ..Default::default()
+        })
+        .unwrap(),
+    );
+    mixed
+        .applications
+        .insert("web".into(), lock.applications["web"].clone());
+    locked
+        .applications
+        .insert("api".into(), Application::default());
+    let original = locked.clone();
+    assert!(locked.apply_lock(&mixed, path.to_str().unwrap()).is_err());
+    assert_eq!(locked, original);
+}
+
+#[test]
+fn build_matrix() {
+    let charm = CharmSource::load("tests/examples/charms/multi").unwrap();
+    let artifacts = [Artifact::parse(
+        "multi",
+        "multi_ubuntu-20.04-amd64-arm64.charm",
+        &charm.run_on(),
+    )
+    .unwrap()];
+
+    let matrix: Vec<_> = charm
+        .build_matrix(&artifacts)
+        .into_iter()
+        .map(|(platform, artifact)| (platform.to_string(), artifact.is_some()))
+        .collect();
+
+    assert_eq!(
+        matrix,
+        vec![
+            ("ubuntu@20.04/amd64".into(), true),
+            ("ubuntu@20.04/arm64".into(), true),
+            ("ubuntu@22.04/amd64".into(), false),
+        ]
+    );
+}
+
+#[test]
+fn build_picks_artifact() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("bundle.yaml");
+    copy_charm("multi", &dir.path().join("multi"));
+
+    let cached = cache_build(&CharmSource::load(dir.path().join("multi")).unwrap());
+    let cache = cached.parent().unwrap();
+    for name in &[
+        "multi_ubuntu-20.04-arm64.charm",
+        "multi_ubuntu-22.04-amd64.charm",
+    ] {
+        fs::write(cache.join(name), "zip").unwrap();
+    }
+
+    let app = |series: Option<&str>, arch: Option<&str>| Application {
+        source: Some("./multi".into()),
+        series: series.map(String::from),
+        constraints: arch.map(|a| format!("arch={}", a).parse().unwrap()),
+        ..Default::default()
+    };
+
+    let mut bundle = Bundle {
+        series: Some(Series::Focal),
+        ..Default::default()
+    };
+    bundle
+        .applications
+        .insert("default".into(), app(None, None));
+    bundle
+        .applications
+        .insert("arm".into(), app(None, Some("arm64")));
+    bundle
+        .applications
+        .insert("jammy".into(), app(Some("jammy"), None));
+    bundle
+        .applications
+        .insert("jammy-arm".into(), app(Some("jammy"), Some("arm64")));
+    bundle
+        .applications
+        .insert("k8s".into(), app(Some("kubernetes"), Some("arm64")));
+    bundle
+        .applications
+        .insert("centos".into(), app(Some("centos7"), Some("arm64")));
+
+    let opts = BuildOptions {
+        keep_going: true,
+        ..Default::default()
+    };
+    let report = bundle
+        .build_with(path.to_str().unwrap(), None, &opts)
+        .unwrap();
+
+    let built: Vec<_> = report
+        .built
+        .iter()
+        .map(|(name, charm)| {
+            let file = PathBuf::from(charm.to_string());
+            (
+                name.as_str(),
+                file.file_name().unwrap().to_string_lossy().to_string(),
+            )
+        })
+        .collect();
+
+    assert_eq!(
+        built,
+        vec![
+            ("default", "multi_ubuntu-20.04-amd64.charm".to_string()),
+            ("arm", "multi_ubuntu-20.04-arm64.charm".to_string()),
+            ("jammy", "multi_ubuntu-22.04-amd64.charm".to_string()),
+            ("k8s", "multi_ubuntu-20.04-arm64.charm".to_string()),
+            ("centos", "multi_ubuntu-20.04-arm64.charm".to_string()),
+        ]
+    );
+
+    assert_eq!(report.failed.len(), 1);
+    assert_eq!(
+        report.failed[0].to_string(),
+        "jammy-arm: None of the charms built for jammy-arm run on jammy/arm64"
+    );
+
+    // The lock is only written when asked for
+    assert!(!BundleLock::path_for(&path).exists());
+}
+
+#[test]
+fn build_native() {
+    let cache = charm_cache();
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("bundle.yaml");
+    copy_charm("web", &dir.path().join("web"));
+    fs::create_dir_all(dir.path().join("web/src")).unwrap();
+    // Builds are cached across test runs, so make sure this one starts from scratch
+    fs::write(
+        dir.path().join("web/src/charm.py"),
+        format!("# {}\nimport ops\n", dir.path().display()),
+    )
+    .unwrap();
+
+    let wheelhouse = dir.path().join("wheelhouse");
+    fs::create_dir(&wheelhouse).unwrap();
+    let mut wheel =
+        zip::ZipWriter::new(fs::File::create(wheelhouse.join("ops-2.0-py3-none-any.whl")).unwrap());
+    wheel
+        .start_file("ops/__init__.py", Default::default())
+        .unwrap();
+    wheel.write_all(b"wheel").unwrap();
+    wheel.finish().unwrap();
+
+    let opts = BuildOptions {
+        native: true,
+        wheelhouse: Some(wheelhouse.clone()),
+        ..Default::default()
+    };
+    let build = || {
+        let mut bundle = Bundle::default();
+        bundle
+            .applications
+            .insert("web".into(), Application::default());
+        let report = bundle
+            .build_with(path.to_str().unwrap(), None, &opts)
+            .unwrap();
+        PathBuf::from(report.built["web"].to_string())
+    };
+
+    let built = build();
+    assert!(built.starts_with(&cache));
+    assert!(built.is_file());
+
+    let mut archive = zip::ZipArchive::new(fs::File::open(&built).unwrap()).unwrap();
+    assert!(archive.by_name("venv/ops/__init__.py").is_ok());
+
+    // Unchanged sources and wheels are taken from the cache
+    fs::write(&built, "cached").unwrap();
+    assert_eq!(build(), built);
+    assert_eq!(fs::read_to_string(&built).unwrap(), "cached");
+
+    // Different wheels are packed again
+    fs::copy(
+        wheelhouse.join("ops-2.0-py3-none-any.whl"),
+        wheelhouse.join("extra-1.0-py3-none-any.whl"),
+    )
+    .unwrap();
+    assert_ne!(build(), built);
+}
+
+#[test]
+fn build_shared_source() {
+    charm_cache();
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("bundle.yaml");
+    copy_charm("web", &dir.path().join("web"));
+    fs::create_dir_all(dir.path().join("web/src")).unwrap();
+    fs::write(
+        dir.path().join("web/src/charm.py"),
+        format!("# {}\nimport ops\n", dir.path().display()),
+    )
+    .unwrap();
+
+    // Applications sharing a source build it once, even when built in parallel
+    let mut bundle = Bundle::default();
+    for name in &["web", "web-admin", "web-api", "web-worker"] {
+        bundle.applications.insert(
+            name.to_string(),
+            Application {
+                source: Some("./web".into()),
+                ..Default::default()
+            },
+        );
+    }
+
+    let opts = BuildOptions {
+        jobs: Some(4),
+        native: true,
+        ..Default::default()
+    };
+    let report = bundle
+        .build_with(path.to_str().unwrap(), None, &opts)
+        .unwrap();
+
+    let built: HashSet<_> = report.built.values().map(ToString::to_string).collect();
+    assert_eq!(report.built.len(), 4);
+    assert_eq!(built.len(), 1);
+}
+
+#[test]
+fn upload_revisions() {
+    let platform = |channel: &str, arch: &str| Platform {
+        name: "ubuntu".into(),
+        channel: channel.into(),
+        arch: arch.into(),
+    };
+    let upload = Upload {
+        charm: "multi".parse().unwrap(),
+        revisions: vec![
+            (platform("20.04", "amd64"), 5),
+            (platform("20.04", "arm64"), 5),
+            (platform("22.04", "amd64"), 6),
+        ]
+        .into_iter()
+        .collect(),
+        resources: IndexMap::new(),
+    };
+
+    assert_eq!(upload.revision(None, None), Some(5));
+    assert_eq!(upload.revision(Some("jammy"), None), Some(6));
+    assert_eq!(upload.revision(Some("22.04"), Some("amd64")), Some(6));
+    assert_eq!(upload.revision(Some("focal"), Some("arm64")), Some(5));
+    assert_eq!(upload.revision(Some("jammy"), Some("arm64")), None);
+}
+
+#[test]
+fn pack_native() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = dir.path().join("web");
+    copy_charm("web", &source);
+
+    for (path, contents) in &[
+        ("src/charm.py", "import ops\n"),
+        ("src/notes.tmp", "scratch"),
+        ("src/__pycache__/charm.cpython-38.pyc", "bytecode"),
+        ("lib/charms/foo/v0/foo.py", "LIBID = 'foo'\n"),
+        ("README.md", "docs"),
+        ("icon.svg", "<svg/>"),
+        ("templates/config.j2", "{{ port }}"),
+        ("build/leftover", "junk"),
+        ("web_ubuntu-20.04-amd64.charm", "zip"),
+        (".git/HEAD", "ref: refs/heads/main"),
+        (".jujuignore", "*.tmp\n"),
+    ] {
+        let path = source.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    let wheelhouse = dir.path().join("wheelhouse");
+    fs::create_dir(&wheelhouse).unwrap();
+    let mut wheel =
+        zip::ZipWriter::new(fs::File::create(wheelhouse.join("ops-2.0-py3-none-any.whl")).unwrap());
+    for name in &[
+        "ops/__init__.py",
+        "ops-2.0.dist-info/METADATA",
+        "ops-2.0.data/scripts/ops",
+    ] {
+        wheel.start_file(*name, Default::default()).unwrap();
+        wheel.write_all(b"wheel").unwrap();
+    }
+    wheel.finish().unwrap();
+
+    let out = dir.path().join("out");
+    let artifacts = CharmSource::load(&source)
+        .unwrap()
+        .pack_native(&out, Some(&wheelhouse))
+        .unwrap();
+
+    assert_eq!(artifacts.len(), 1);
+    assert_eq!(artifacts[0].bases[0].channel, "20.04");
+    assert_eq!(artifacts[0].path.parent(), Some(out.as_path()));
+
+    let mut archive = zip::ZipArchive::new(fs::File::open(&artifacts[0].path).unwrap()).unwrap();
+    let mut names: Vec<_> = archive.file_names().map(String::from).collect();
+    names.sort();
+    assert_eq!(
+        names,
+        vec![
+            "README.md",
+            "charmcraft.yaml",
+            "config.yaml",
+            "dispatch",
+            "icon.svg",
+            "lib/charms/foo/v0/foo.py",
+            "manifest.yaml",
+            "metadata.yaml",
+            "src/charm.py",
+            "templates/config.j2",
+            "venv/ops-2.0.dist-info/METADATA",
+            "venv/ops/__init__.py",
+        ]
+    );
+    assert_eq!(
+        archive
+            .by_name("dispatch")
+            .unwrap()
+            .unix_mode()
+            .map(|m| m & 0o777),
+        Some(0o755)
+    );
+
+    let mut manifest = String::new();
+    archive
+        .by_name("manifest.yaml")
+        .unwrap()
+        .read_to_string(&mut manifest)
+        .unwrap();
+    let manifest: serde_yaml::Value = serde_yaml::from_str(&manifest).unwrap();
+    assert_eq!(manifest["bases"][0]["channel"].as_str(), Some("20.04"));
+
+    let packed = CharmSource::load(&artifacts[0].path).unwrap();
+    assert_eq!(packed.metadata.name, "web");
+
+    // Dependencies can't be left out just because there's no wheelhouse
+    fs::write(source.join("requirements.txt"), "# Runtime\n\n").unwrap();
+    let charm = CharmSource::load(&source).unwrap();
+    assert!(charm.pack_native(&out, None).is_ok());
+
+    fs::write(source.join("requirements.txt"), "# Runtime\nops>=2.0\n").unwrap();
+    let err = charm.pack_native(&out, None).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Can't pack web without charmcraft, as requirements.txt lists dependencies, but there are no wheels to install"
+    );
+    assert!(charm.pack_native(&out, Some(&wheelhouse)).is_ok());
+
+    // Charms with parts that need compiling still need charmcraft
+    let mut charmcraft = fs::read_to_string(source.join("charmcraft.yaml")).unwrap();
+    charmcraft.push_str("parts:\n  charm: {}\n  engine:\n    plugin: rust\n");
+    fs::write(source.join("charmcraft.yaml"), charmcraft).unwrap();
+
+    let err = CharmSource::load(&source)
+        .unwrap()
+        .pack_native(&out, None)
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Can't pack web without charmcraft, as parts need compiling: engine"
+    );
+}
+
+#[test]
+fn resolve_includes() {
+    let path = "tests/examples/bundle-include.yaml";
+    let mut bundle = Bundle::load(path).unwrap();
+
+    let mut foo = bundle.applications["foo"].clone();
+    foo.resolve_includes("foo", path).unwrap();
+    assert_eq!(
+        foo.options["motd"],
+        Value::String("Welcome to the machine\n".into())
+    );
+    assert_eq!(foo.options["key"], Value::String("AAFiaW5hcnk=".into()));
+    assert_eq!(foo.options["plain"], Value::String("hello".into()));
+
+    let err = bundle.resolve_includes(path).unwrap_err().to_string();
+    assert!(err.starts_with("Couldn't include `include/missing.txt` in option `motd` for bar:"));
+}
+
+#[test]
+fn validate_storage_directives() {
+    let bytes = fs::read(PathBuf::from("tests/examples/kubernetes-metadata.yaml")).unwrap();
+    let metadata = from_slice(&bytes).unwrap();
+
+    let mut app = Application {
+        storage: [("logs".to_string(), "10G".try_into().unwrap())].into(),
+        ..Default::default()
+    };
+    app.validate_directives("foo", &metadata).unwrap();
+
+    app.storage = [("log".to_string(), "10G".try_into().unwrap())].into();
+    assert!(app.validate_directives("foo", &metadata).is_err());
+
+    app.storage = IndexMap::new();
+    app.devices = [("gpu".to_string(), "nvidia.com/gpu".try_into().unwrap())].into();
+    assert!(app.validate_directives("foo", &metadata).is_err());
+}
+
+fn load_charms() -> HashMap<String, CharmSource> {
+    ["db", "web", "logger"]
+        .iter()
+        .map(|name| {
+            let path = PathBuf::from("tests/examples/charms").join(name);
+            (name.to_string(), CharmSource::load(path).unwrap())
+        })
+        .collect()
+}
+
+#[test]
+fn bundle_graph() {
+    let bundle = Bundle::load("tests/examples/bundle-graph.yaml").unwrap();
+    let graph = bundle.graph(&load_charms());
+
+    assert_eq!(
+        graph.to_dot(),
+        "graph bundle {\n    \"web\";\n    \"db\";\n    \"logger\" [style=dashed];\n    \
+         \"prometheus\" [shape=box];\n    \"web\" -- \"db\" [label=\"mysql\"];\n    \
+         \"logger\" -- \"web\" [label=\"juju-info\"];\n    \"web\" -- \"prometheus\";\n}\n"
+    );
+
+    assert_eq!(
+        graph.to_mermaid(),
+        "graph LR\n    n0[\"web\"]\n    n1[\"db\"]\n    n2([\"logger\"])\n    n3[[\"prometheus\"]]\n    \
+         n0 ---|\"mysql\"| n1\n    n2 ---|\"juju-info\"| n0\n    n0 --- n3\n"
+    );
+
+    // Quotes are escaped as each format expects
+    let graph = Graph {
+        nodes: vec![Node {
+            name: "say \"hi\" #1".into(),
+            kind: NodeKind::Application,
+        }],
+        edges: vec![],
+    };
+    assert_eq!(
+        graph.to_dot(),
+        "graph bundle {\n    \"say \\\"hi\\\" #1\";\n}\n"
+    );
+    assert_eq!(
+        graph.to_mermaid(),
+        "graph LR\n    n0[\"say #quot;hi#quot; #35;1\"]\n"
+    );
+}
+
+#[test]
+fn validate_bundle() {
+    let bundle = Bundle::load(PathBuf::from("tests/examples/bundle-validate.yaml")).unwrap();
+    let charms = load_charms();
+
+    let diagnostics = bundle
+        .validate(&charms)
+        .into_iter()
+        .map(|d| {
+            assert_eq!(d.severity, Severity::Error);
+            d.to_string()
+        })
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        diagnostics,
+        vec![
+            "error: db: Charm has no config option `colour`",
+            "error: db: Config option `port` should be of type int",
+            "error: db: datta: Charm has no storage named `datta`",
+            "error: logger: Subordinate charms must have a scale of 0, not 1",
+            "error: web: Placement `3` refers to an undefined machine",
+            "error: web: Charm has no resource `web-config`",
+            "error: Relation `web db` is ambiguous, it could use any of: \
+             web:database db:db, web:replica db:db",
+            "error: Relation `web:website db:db` has no endpoints with a matching interface",
+            "error: web: Charm has no relation endpoint `cache`",
+            "error: Relation `web nginx` refers to unknown application `nginx`",
+        ]
+    );
+}
+
+#[test]
+fn resolve_relations() {
+    let charms = load_charms();
+
+    let mut bundle = Bundle {
+        relations: vec![
+            relation("web:database", "db"),
+            relation("db", "logger"),
+            relation("web", "mysql"),
+        ],
+        ..Default::default()
+    };
+    bundle.resolve_relations(&charms).unwrap();
+
+    assert_eq!(
+        bundle.relations,
+        vec![
+            relation("web:database", "db:db"),
+            relation("db:juju-info", "logger:juju-info"),
+            relation("web", "mysql"),
+        ]
+    );
+
+    bundle.relations = vec![relation("web", "db")];
+    let err = bundle.resolve_relations(&charms).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Relation `web db` is ambiguous, it could use any of: \
+         web:database db:db, web:replica db:db"
+    );
+
+    bundle.relations = vec![relation("web:website", "db")];
+    assert!(bundle.resolve_relations(&charms).is_err());
+}
+
 #[test]
 fn parse_controller_yaml() {
     let bytes = fs::read(PathBuf::from("tests/examples/controllers.yaml")).unwrap();
@@ -208,10 +1575,44 @@ fn parse_metadata_yaml() {
         requires,
         peer,
         storage,
-        devices: HashMap::new(),
-        extra_bindings: HashMap::new(),
+        devices: IndexMap::new(),
+        extra_bindings: IndexMap::new(),
         series: None,
     };
 
     assert_eq!(parsed, expected);
 }
+
+#[test]
+fn parse_base_container_without_resource() {
+    use juju::charm_source as cs;
+
+    let parsed: cs::Container = serde_yaml::from_str(
+        "bases:\n  - name: ubuntu\n    channel: '22.04'\n    architectures: [amd64]\nmounts:\n  - storage: logs\n    location: /logs\n",
+    )
+    .unwrap();
+
+    assert_eq!(
+        parsed,
+        cs::Container::Base(cs::BaseContainer {
+            bases: vec![cs::ContainerBase {
+                name: "ubuntu".into(),
+                channel: "22.04".into(),
+                architectures: vec!["amd64".into()],
+            }],
+            mounts: vec![cs::ContainerMount {
+                storage: "logs".into(),
+                location: "/logs".into(),
+            }],
+        })
+    );
+
+    let parsed: cs::Container = serde_yaml::from_str("mounts: []\n").unwrap();
+    assert_eq!(
+        parsed,
+        cs::Container::Base(cs::BaseContainer {
+            bases: vec![],
+            mounts: vec![],
+        })
+    );
+}